@@ -0,0 +1,160 @@
+use std::f32::consts::PI;
+use std::fs;
+
+use cgmath::Point3;
+use cgmath::prelude::*;
+use glfw::{Context, Glfw, Window};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Pose;
+use crate::coords::SphericalPoint3;
+use crate::fps_calculator::FpsCalculator;
+use crate::observer::RenderLoopObserver;
+use crate::xmas_tree::scene::Scene;
+
+/// Simulation advances by the same step every frame, so every run shows the same frames
+const BENCHMARK_TIMESTEP: f32 = 1. / 60.;
+/// Differences smaller than that fraction are treated as noise when comparing with a baseline
+const NOISE: f32 = 0.05;
+
+/// Frame times in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub scene: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: usize,
+    pub min: f32,
+    pub avg: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl BenchmarkReport {
+    /// Frame times are in seconds
+    pub fn new(scene: &str, width: u32, height: u32, frame_times: &[f32]) -> Self {
+        let mut sorted: Vec<f32> = frame_times.iter().map(|t| t * 1000.).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let sum: f32 = sorted.iter().sum();
+        BenchmarkReport {
+            scene: scene.to_owned(),
+            width,
+            height,
+            frames: sorted.len(),
+            min: sorted.first().copied().unwrap_or(0.),
+            avg: if sorted.is_empty() { 0. } else { sum / sorted.len() as f32 },
+            p95: percentile(&sorted, 95.),
+            p99: percentile(&sorted, 99.),
+            max: sorted.last().copied().unwrap_or(0.),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_str(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let source = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn metrics(&self) -> [(&'static str, f32); 5] {
+        [("min", self.min), ("avg", self.avg), ("p95", self.p95), ("p99", self.p99), ("max", self.max)]
+    }
+
+    pub fn summary(&self) -> String {
+        let metrics: Vec<String> = self.metrics().iter().map(|(name, value)| format!("{} {:.3} ms", name, value)).collect();
+        format!("{} frames of {} at {}x{}: {}", self.frames, self.scene, self.width, self.height, metrics.join(", "))
+    }
+
+    /// Every metric next to the baseline one together with the relative change
+    pub fn compare(&self, baseline: &BenchmarkReport) -> String {
+        let mut lines = vec![format!("{:<4} {:>10} {:>10} {:>8}", "", "baseline", "current", "change")];
+        for ((name, current), (_, base)) in self.metrics().iter().zip(baseline.metrics().iter()) {
+            let change = if *base > 0. { (current - base) / base } else { 0. };
+            let verdict = if change > NOISE { "slower" } else if change < -NOISE { "faster" } else { "" };
+            lines.push(format!("{:<4} {:>10.3} {:>10.3} {:>+7.1}% {}", name, base, current, 100. * change, verdict).trim_end().to_owned());
+        }
+        if (self.scene.as_str(), self.width, self.height) != (baseline.scene.as_str(), baseline.width, baseline.height) {
+            lines.push(format!("Warning: baseline was taken with {} at {}x{}", baseline.scene, baseline.width, baseline.height));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f32], percent: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.;
+    }
+    let rank = (percent / 100. * sorted.len() as f32).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
+
+/// Renders given number of frames as fast as possible with vsync off, while the camera makes one full orbit around the point it looks at.
+/// Warm-up frames are rendered before from the starting point, while shaders get compiled and buffers settle, and are not measured.
+/// Returns durations of the measured frames in seconds.
+pub fn run(glfw: &mut Glfw, window: &mut Window, scene: &mut Scene, frames: usize, warmup: usize) -> Vec<f32> {
+    glfw.set_swap_interval(glfw::SwapInterval::None);
+    let start = scene.camera.pose();
+    let orbit = SphericalPoint3::from(Point3::from_vec(start.eye - start.look_at));
+    let mut fps_calculator = FpsCalculator::new();
+    let mut frame_times = Vec::with_capacity(frames);
+    for frame in 0..warmup + frames {
+        if window.should_close() {
+            break;
+        }
+        let angle = 2. * PI * frame.saturating_sub(warmup) as f32 / frames as f32;
+        let offset: Point3<f32> = SphericalPoint3::new(orbit.r, orbit.theta, orbit.phi + angle).into();
+        scene.camera.follow(Some(Pose { eye: start.look_at + offset.to_vec(), ..start }));
+        scene.next_frame(BENCHMARK_TIMESTEP);
+        scene.draw();
+        window.swap_buffers();
+        glfw.poll_events();
+        fps_calculator.tick();
+        if frame < warmup {
+            continue;
+        }
+        if let Some(frame_time) = fps_calculator.frame_times().last() {
+            frame_times.push(*frame_time);
+        }
+    }
+    scene.camera.follow(None);
+    frame_times
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use crate::benchmark::{BenchmarkReport, percentile};
+
+    #[rstest(percent, expected,
+    case(50., 5.),
+    case(95., 10.),
+    case(99., 10.),
+    case(10., 1.),
+    )]
+    fn finds_percentiles(percent: f32, expected: f32) {
+        let sorted: Vec<f32> = (1..=10).map(|v| v as f32).collect();
+        assert_eq!(percentile(&sorted, percent), expected);
+    }
+
+    #[test]
+    fn summarizes_frame_times() {
+        let report = BenchmarkReport::new("scene.ron", 640, 480, &[0.02, 0.01, 0.03]);
+        assert_eq!((report.frames, report.min, report.max), (3, 10., 30.));
+        assert!((report.avg - 20.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn marks_regressions() {
+        let baseline = BenchmarkReport::new("scene.ron", 640, 480, &[0.01, 0.01]);
+        let current = BenchmarkReport::new("scene.ron", 640, 480, &[0.02, 0.02]);
+        let comparison = current.compare(&baseline);
+        assert!(comparison.lines().skip(1).all(|l| l.ends_with("slower")), "{}", comparison);
+        assert!(baseline.compare(&baseline).lines().skip(1).all(|l| !l.contains("slower") && !l.contains("faster")));
+    }
+}
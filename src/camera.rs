@@ -1,28 +1,166 @@
 extern crate gl;
 
 use std::{mem, ptr};
+use std::f32::consts::PI;
 use std::os::raw::c_void;
 
-use cgmath::{Deg, Matrix4, perspective, Point3, vec3, Vector4};
+use cgmath::{Deg, Matrix4, ortho, perspective, Point3, vec3, Vector3, Vector4};
 use cgmath::prelude::*;
 use glfw::Window;
 
 use crate::coords::SphericalPoint3;
+use crate::picking::Ray;
 use crate::shader::CAMERA_UBO_BINDING_POINT;
 
+/// How fast the camera stops after being pushed, per second
+const DAMPING: f32 = 8.;
+/// Polar angle is kept that far from the poles, so the camera never flips over
+const MIN_THETA: f32 = 0.05;
+const MIN_DISTANCE: f32 = 2.;
+const MAX_DISTANCE: f32 = 60.;
+/// Each step of the scroll wheel changes the distance by that fraction
+const ZOOM_STEP: f32 = 0.1;
+/// Free-fly speed in units per second
+const FLY_SPEED: f32 = 6.;
+const MIN_FOV: f32 = 10.;
+const MAX_FOV: f32 = 120.;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CameraMode {
+    /// Camera moves on a sphere around the pivot looking at look_at, both can be panned
+    Orbit,
+    /// First person camera moving freely with WASD keys
+    Fly,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProjectionKind {
+    Perspective,
+    Orthographic,
+}
+
+/// Shape of the volume the camera sees, field of view is vertical
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Projection {
+    pub kind: ProjectionKind,
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection { kind: ProjectionKind::Perspective, fov: Deg(45.), near: 0.1, far: 100. }
+    }
+}
+
+impl Projection {
+    /// Orthographic projection shows as much as the perspective one does at given distance from the camera,
+    /// so switching between them keeps the point the camera looks at framed the same
+    pub fn matrix(&self, aspect: f32, distance: f32) -> Matrix4<f32> {
+        match self.kind {
+            ProjectionKind::Perspective => perspective(self.fov, aspect, self.near, self.far),
+            ProjectionKind::Orthographic => {
+                let half_height = distance * (self.fov / 2.).tan();
+                let half_width = half_height * aspect;
+                ortho(-half_width, half_width, -half_height, half_height, self.near, self.far)
+            }
+        }
+    }
+}
+
+/// Everything needed to place the camera, field of view is vertical
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pose {
+    pub eye: Point3<f32>,
+    pub look_at: Point3<f32>,
+    pub fov: Deg<f32>,
+}
+
+/// Change of a value that slowly dies out after being pushed, gives the camera its smoothness
+#[derive(Debug, Default, Copy, Clone)]
+struct Inertia {
+    velocity: f32,
+}
+
+impl Inertia {
+    /// The value changes by the given amount in total
+    fn push(&mut self, amount: f32) {
+        self.velocity += amount * DAMPING;
+    }
+
+    /// Returns how much the value changes in dt seconds
+    fn step(&mut self, dt: f32) -> f32 {
+        let decay = (-DAMPING * dt).exp();
+        let change = self.velocity * (1. - decay) / DAMPING;
+        self.velocity *= decay;
+        change
+    }
+
+    fn stop(&mut self) {
+        self.velocity = 0.;
+    }
+}
+
 pub struct Camera {
+    mode: CameraMode,
+    /// orbit position relative to the pivot, the pivot is the origin moved by pan
     position: SphericalPoint3<f32>,
     look_at: Point3<f32>,
+    pan: Vector3<f32>,
+    fly_position: Point3<f32>,
+    /// direction the flying camera looks in, r is ignored
+    fly_direction: SphericalPoint3<f32>,
+    fly_velocity: Vector3<f32>,
+    /// requested movement in camera space: x to the right, y up, z forward
+    fly_input: Vector3<f32>,
+    horizontal: Inertia,
+    vertical: Inertia,
+    zoom: Inertia,
+    pan_x: Inertia,
+    pan_y: Inertia,
+    projection: Projection,
+    /// pose given from outside, e.g. by a camera path, overrides both modes while set
+    scripted: Option<Pose>,
+    /// applied after the projection to draw only a part of the picture, see capture::tile_crop()
+    crop: Matrix4<f32>,
     ubo: u32,
+    /// cursor positions are given in window coordinates
     window_width: f32,
     window_height: f32,
+    /// aspect ratio is taken from the framebuffer, on HiDPI displays its size differs from the window size
+    framebuffer_width: f32,
+    framebuffer_height: f32,
 }
 
 impl Camera {
-    pub fn new(position: SphericalPoint3<f32>, look_at: Point3<f32>, window: &Window) -> Self {
+    pub fn new(position: SphericalPoint3<f32>, look_at: Point3<f32>, projection: Projection, window: &Window) -> Self {
         let (window_width, window_height) = window.get_size();
+        let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
         let ubo = Camera::setup_camera_ubo();
-        let camera = Camera { position, look_at, ubo, window_width: window_width as f32, window_height: window_height as f32 };
+        let camera = Camera {
+            mode: CameraMode::Orbit,
+            position,
+            look_at,
+            pan: Vector3::zero(),
+            fly_position: position.into(),
+            fly_direction: SphericalPoint3::new(1., PI / 2., 0.),
+            fly_velocity: Vector3::zero(),
+            fly_input: Vector3::zero(),
+            horizontal: Inertia::default(),
+            vertical: Inertia::default(),
+            zoom: Inertia::default(),
+            pan_x: Inertia::default(),
+            pan_y: Inertia::default(),
+            projection,
+            scripted: None,
+            crop: Matrix4::identity(),
+            ubo,
+            window_width: window_width as f32,
+            window_height: window_height as f32,
+            framebuffer_width: framebuffer_width as f32,
+            framebuffer_height: framebuffer_height as f32,
+        };
         camera.update_uniforms();
         camera
     }
@@ -46,32 +184,273 @@ impl Camera {
         let vector3_size = mem::size_of::<Vector4<f32>>() as isize;
         unsafe {
             gl::BindBuffer(gl::UNIFORM_BUFFER, self.ubo);
-            let pos: Point3<f32> = self.position.into();
+            let pos = self.eye();
             gl::BufferSubData(gl::UNIFORM_BUFFER, 0, vector3_size, pos.as_ptr() as *const c_void);
 
-            let view: Matrix4<f32> = Matrix4::look_at(self.position.into(), self.look_at, vec3(0.0, 1.0, 0.0));
+            let view = self.view();
             gl::BufferSubData(gl::UNIFORM_BUFFER, vector3_size, matrix_size, view.as_ptr() as *const c_void);
-            let projection = perspective(Deg(45.0), self.window_width / self.window_height, 0.1, 100.0);
+            let projection = self.projection();
             gl::BufferSubData(gl::UNIFORM_BUFFER, vector3_size + matrix_size, matrix_size, projection.as_ptr() as *const c_void);
 
             gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
         }
     }
 
+    /// Position of the camera in the world
+    pub fn eye(&self) -> Point3<f32> {
+        if let Some(pose) = self.scripted {
+            return pose.eye;
+        }
+        match self.mode {
+            CameraMode::Orbit => {
+                let orbit: Point3<f32> = self.position.into();
+                orbit + self.pan
+            }
+            CameraMode::Fly => self.fly_position,
+        }
+    }
+
+    fn forward(&self) -> Vector3<f32> {
+        let direction: Point3<f32> = self.fly_direction.into();
+        direction.to_vec()
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        if let Some(pose) = self.scripted {
+            return Matrix4::look_at(pose.eye, pose.look_at, vec3(0.0, 1.0, 0.0));
+        }
+        match self.mode {
+            CameraMode::Orbit => Matrix4::look_at(self.eye(), self.look_at + self.pan, vec3(0.0, 1.0, 0.0)),
+            CameraMode::Fly => Matrix4::look_at_dir(self.fly_position, self.forward(), vec3(0.0, 1.0, 0.0)),
+        }
+    }
+
+    pub fn projection(&self) -> Matrix4<f32> {
+        let projection = Projection { fov: self.scripted.map_or(self.projection.fov, |pose| pose.fov), ..self.projection };
+        self.crop * projection.matrix(self.framebuffer_width / self.framebuffer_height, self.focus_distance())
+    }
+
+    /// Makes the camera show only a part of the picture, identity shows all of it
+    pub fn set_crop(&mut self, crop: Matrix4<f32>) {
+        self.crop = crop;
+        self.update_uniforms();
+    }
+
+    /// Distance to the point the camera looks at, the flying camera uses the orbit distance
+    fn focus_distance(&self) -> f32 {
+        match (self.scripted, self.mode) {
+            (Some(pose), _) => (pose.look_at - pose.eye).magnitude(),
+            (None, CameraMode::Orbit) => {
+                let orbit: Point3<f32> = self.position.into();
+                (self.look_at - orbit).magnitude()
+            }
+            (None, CameraMode::Fly) => self.position.r,
+        }
+    }
+
+    /// Moves the clip planes by multiplying their distances, changes that would put the near plane behind the far one are ignored
+    pub fn scale_clip_planes(&mut self, near_factor: f32, far_factor: f32) {
+        let (near, far) = (self.projection.near * near_factor, self.projection.far * far_factor);
+        if near < far {
+            self.projection.near = near;
+            self.projection.far = far;
+            self.update_uniforms();
+        }
+    }
+
+    pub fn toggle_orthographic(&mut self) {
+        self.projection.kind = match self.projection.kind {
+            ProjectionKind::Perspective => ProjectionKind::Orthographic,
+            ProjectionKind::Orthographic => ProjectionKind::Perspective,
+        };
+        self.update_uniforms();
+    }
+
+    /// Widens or narrows the field of view by given angle, it's kept between 10 and 120 degrees
+    pub fn change_fov(&mut self, change: Deg<f32>) {
+        self.projection.fov = Deg((self.projection.fov + change).0.clamp(MIN_FOV, MAX_FOV));
+        self.update_uniforms();
+    }
+
+    /// Ray going from the camera through given cursor position, in window coordinates
+    pub fn ray(&self, cursor_x: f64, cursor_y: f64) -> Ray {
+        let x = 2. * cursor_x as f32 / self.window_width - 1.;
+        let y = 1. - 2. * cursor_y as f32 / self.window_height;
+        Ray::from_ndc(x, y, &self.view(), &self.projection())
+    }
+
+    /// Current pose, the flying camera looks at the point one unit in front of it
+    pub fn pose(&self) -> Pose {
+        let look_at = match (self.scripted, self.mode) {
+            (Some(pose), _) => pose.look_at,
+            (None, CameraMode::Orbit) => self.look_at + self.pan,
+            (None, CameraMode::Fly) => self.fly_position + self.forward(),
+        };
+        Pose { eye: self.eye(), look_at, fov: self.scripted.map_or(self.projection.fov, |pose| pose.fov) }
+    }
+
+    /// Makes the camera take given pose until it's called with None, user's camera is kept meanwhile
+    pub fn follow(&mut self, pose: Option<Pose>) {
+        self.scripted = pose;
+        self.update_uniforms();
+    }
+
     pub fn on_window_resize(&mut self, window: &Window) {
         let (window_width, window_height) = window.get_size();
+        let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
         self.window_width = window_width as f32;
         self.window_height = window_height as f32;
+        self.set_framebuffer_size(framebuffer_width as u32, framebuffer_height as u32);
+    }
+
+    /// Size of the framebuffer being drawn into, used for the aspect ratio
+    pub fn set_framebuffer_size(&mut self, width: u32, height: u32) {
+        self.framebuffer_width = width as f32;
+        self.framebuffer_height = height as f32;
         self.update_uniforms();
     }
 
-    pub fn rotate_horizontally(&mut self, angle: f32) {
-        self.position.phi += angle;
+    /// Switches between orbit and free-fly, the flying camera starts where the orbiting one was
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Orbit => {
+                self.fly_position = self.eye();
+                let direction = (self.look_at + self.pan) - self.eye();
+                self.fly_direction = SphericalPoint3::from(Point3::from_vec(direction));
+                self.fly_direction.r = 1.;
+                self.fly_velocity = Vector3::zero();
+                CameraMode::Fly
+            }
+            CameraMode::Fly => CameraMode::Orbit,
+        };
+        self.horizontal.stop();
+        self.vertical.stop();
+        self.zoom.stop();
+        self.pan_x.stop();
+        self.pan_y.stop();
         self.update_uniforms();
     }
 
+    /// Orbits around the pivot or turns the flying camera, positive angle goes to the right
+    pub fn rotate_horizontally(&mut self, angle: f32) {
+        match self.mode {
+            CameraMode::Orbit => self.horizontal.push(angle),
+            CameraMode::Fly => self.horizontal.push(-angle),
+        }
+    }
+
+    /// Positive angle moves the orbiting camera down or makes the flying camera look down
     pub fn rotate_vertically(&mut self, angle: f32) {
-        self.position.theta += angle;
+        self.vertical.push(angle);
+    }
+
+    /// Mouse drag by given fraction of the window size, orbit drags the scene, free-fly looks around
+    pub fn drag(&mut self, x: f32, y: f32) {
+        match self.mode {
+            CameraMode::Orbit => {
+                self.rotate_horizontally(-4. * PI * x);
+                self.rotate_vertically(-4. * PI * y);
+            }
+            CameraMode::Fly => {
+                self.rotate_horizontally(PI * x);
+                self.rotate_vertically(PI * y);
+            }
+        }
+    }
+
+    /// Scroll wheel steps, positive ones get the orbiting camera closer
+    pub fn zoom(&mut self, steps: f32) {
+        if self.mode == CameraMode::Orbit {
+            // distance changes by the same fraction with every step, no matter how far the camera is
+            self.zoom.push(-steps * ZOOM_STEP);
+        }
+    }
+
+    /// Moves both the pivot and the point the orbiting camera looks at by given fraction of the window size
+    pub fn pan(&mut self, x: f32, y: f32) {
+        if self.mode == CameraMode::Orbit {
+            self.pan_x.push(-x * self.position.r);
+            self.pan_y.push(y * self.position.r);
+        }
+    }
+
+    /// Requested free-fly movement in camera space, every component from -1 to 1: x to the right, y up, z forward
+    pub fn set_fly_input(&mut self, input: Vector3<f32>) {
+        self.fly_input = input;
+    }
+
+    /// Moves the camera according to its inertia, dt is real time, so the camera moves even when the simulation is paused
+    pub fn update(&mut self, dt: f32) {
+        let horizontal = self.horizontal.step(dt);
+        let vertical = self.vertical.step(dt);
+        match self.mode {
+            CameraMode::Orbit => {
+                self.position.phi += horizontal;
+                self.position.theta = Self::clamp_theta(self.position.theta + vertical, &mut self.vertical);
+                let distance = self.position.r * self.zoom.step(dt).exp();
+                if !(MIN_DISTANCE..=MAX_DISTANCE).contains(&distance) {
+                    self.zoom.stop();
+                }
+                self.position.r = distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
+
+                let view = self.view();
+                let right = vec3(view.x.x, view.y.x, view.z.x);
+                let up = vec3(view.x.y, view.y.y, view.z.y);
+                self.pan += right * self.pan_x.step(dt) + up * self.pan_y.step(dt);
+            }
+            CameraMode::Fly => {
+                self.fly_direction.phi += horizontal;
+                self.fly_direction.theta = Self::clamp_theta(self.fly_direction.theta + vertical, &mut self.vertical);
+
+                let forward = self.forward();
+                let right = forward.cross(vec3(0., 1., 0.)).normalize();
+                let input = self.fly_input;
+                let target = (right * input.x + vec3(0., input.y, 0.) + forward * input.z) * FLY_SPEED;
+                self.fly_velocity += (target - self.fly_velocity) * (1. - (-DAMPING * dt).exp());
+                self.fly_position += self.fly_velocity * dt;
+            }
+        }
         self.update_uniforms();
     }
+
+    fn clamp_theta(theta: f32, inertia: &mut Inertia) -> f32 {
+        if !(MIN_THETA..=PI - MIN_THETA).contains(&theta) {
+            inertia.stop();
+        }
+        theta.clamp(MIN_THETA, PI - MIN_THETA)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use cgmath::Point3;
+
+    use crate::camera::{Inertia, Projection, ProjectionKind};
+
+    #[rstest(steps, dt,
+    case(1, 10.),
+    case(100, 0.1),
+    case(1000, 0.016),
+    )]
+    fn inertia_moves_by_pushed_amount(steps: usize, dt: f32) {
+        let mut inertia = Inertia::default();
+        inertia.push(2.);
+        let total: f32 = (0..steps).map(|_| inertia.step(dt)).sum();
+        assert!((total - 2.).abs() < 1e-3, "moved by {}", total);
+    }
+
+    #[test]
+    fn orthographic_projection_frames_like_perspective_at_given_distance() {
+        let perspective = Projection::default();
+        let orthographic = Projection { kind: ProjectionKind::Orthographic, ..perspective };
+        let point = Point3::new(1., 2., -10.).to_homogeneous();
+        let project = |projection: Projection| {
+            let p = projection.matrix(1.5, 10.) * point;
+            (p.x / p.w, p.y / p.w)
+        };
+        let (expected, actual) = (project(perspective), project(orthographic));
+        assert!((expected.0 - actual.0).abs() < 1e-5 && (expected.1 - actual.1).abs() < 1e-5, "{:?} vs {:?}", expected, actual);
+    }
 }
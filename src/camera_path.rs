@@ -0,0 +1,257 @@
+use std::fs;
+use std::ops::{Add, Mul, Sub};
+
+use cgmath::{Deg, Point3, Vector3};
+use cgmath::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Pose;
+use crate::coords::SphericalPoint3;
+
+pub const DEFAULT_CAMERA_PATH: &str = "camera_path.ron";
+/// Time between keyframes captured one after another, in seconds
+const CAPTURE_INTERVAL: f32 = 2.;
+
+fn default_fov() -> f32 {
+    45.
+}
+
+/// Spherical angles are in radians, as in the camera of scene description
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KeyframePosition {
+    Spherical { r: f32, theta: f32, phi: f32 },
+    Cartesian([f32; 3]),
+}
+
+impl KeyframePosition {
+    fn point(&self) -> Point3<f32> {
+        match *self {
+            KeyframePosition::Spherical { r, theta, phi } => SphericalPoint3::new(r, theta, phi).into(),
+            KeyframePosition::Cartesian(position) => position.into(),
+        }
+    }
+}
+
+/// Where the camera is at given time, field of view is vertical and in degrees
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: KeyframePosition,
+    pub look_at: [f32; 3],
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+}
+
+/// Keyframes sorted by time, the camera goes through all of them along a Catmull-Rom spline
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(keyframes: Vec<Keyframe>) -> Result<Self, String> {
+        for (i, pair) in keyframes.windows(2).enumerate() {
+            if pair[1].time <= pair[0].time {
+                return Err(format!("keyframes[{}].time: has to be greater than time of the previous keyframe", i + 1));
+            }
+        }
+        for (i, keyframe) in keyframes.iter().enumerate() {
+            if !(keyframe.fov > 0. && keyframe.fov < 180.) {
+                return Err(format!("keyframes[{}].fov: has to be between 0 and 180 degrees", i));
+            }
+        }
+        Ok(CameraPath { keyframes })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let keyframes: Vec<Keyframe> = ron::de::from_str(&source).map_err(|e| format!("{}: {}", path, e))?;
+        Self::new(keyframes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let source = ron::ser::to_string_pretty(&self.keyframes, PrettyConfig::default()).map_err(|e| e.to_string())?;
+        fs::write(path, source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Adds the pose as a keyframe a moment after the last one
+    pub fn capture(&mut self, pose: &Pose) {
+        let time = self.keyframes.last().map_or(0., |k| k.time + CAPTURE_INTERVAL);
+        let eye = SphericalPoint3::from(pose.eye);
+        self.keyframes.push(Keyframe {
+            time,
+            position: KeyframePosition::Spherical { r: eye.r, theta: eye.theta, phi: eye.phi },
+            look_at: pose.look_at.into(),
+            fov: pose.fov.0,
+        });
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keyframes.first().map_or(0., |k| k.time)
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keyframes.last().map_or(0., |k| k.time)
+    }
+
+    /// Pose at given time, before the first and after the last keyframe the camera stands still
+    pub fn pose(&self, time: f32) -> Option<Pose> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        if last == 0 {
+            return Some(Self::keyframe_pose(&self.keyframes[0]));
+        }
+        let segment = self.keyframes.iter().rposition(|k| k.time <= time).unwrap_or(0).min(last - 1);
+        let (start, end) = (self.keyframes[segment].time, self.keyframes[segment + 1].time);
+        let u = ((time - start) / (end - start)).clamp(0., 1.);
+
+        let eye = self.interpolate(segment, u, |k| k.position.point().to_vec());
+        let look_at = self.interpolate(segment, u, |k| Vector3::from(k.look_at));
+        let fov = self.interpolate(segment, u, |k| k.fov);
+        Some(Pose { eye: Point3::from_vec(eye), look_at: Point3::from_vec(look_at), fov: Deg(fov) })
+    }
+
+    fn keyframe_pose(keyframe: &Keyframe) -> Pose {
+        Pose { eye: keyframe.position.point(), look_at: keyframe.look_at.into(), fov: Deg(keyframe.fov) }
+    }
+
+    /// Cubic Hermite curve between keyframes segment and segment + 1, tangents are Catmull-Rom ones
+    /// computed from neighbouring keyframes, which also works for keyframes that aren't evenly spaced in time
+    fn interpolate<V, F>(&self, segment: usize, u: f32, value: F) -> V
+        where V: Copy + Add<Output=V> + Sub<Output=V> + Mul<f32, Output=V>, F: Fn(&Keyframe) -> V {
+        let last = self.keyframes.len() - 1;
+        let tangent = |i: usize| {
+            let (previous, next) = (i.saturating_sub(1), (i + 1).min(last));
+            let (a, b) = (&self.keyframes[previous], &self.keyframes[next]);
+            (value(b) - value(a)) * (1. / (b.time - a.time))
+        };
+        let (p0, p1) = (value(&self.keyframes[segment]), value(&self.keyframes[segment + 1]));
+        let h = self.keyframes[segment + 1].time - self.keyframes[segment].time;
+        let (m0, m1) = (tangent(segment) * h, tangent(segment + 1) * h);
+
+        let (u2, u3) = (u * u, u * u * u);
+        p0 * (2. * u3 - 3. * u2 + 1.) + m0 * (u3 - 2. * u2 + u) + p1 * (-2. * u3 + 3. * u2) + m1 * (u3 - u2)
+    }
+}
+
+/// Plays a camera path, optionally looping it
+pub struct PathPlayer {
+    pub path: CameraPath,
+    pub looping: bool,
+    /// time since the start of the path, None when not playing
+    time: Option<f32>,
+}
+
+impl PathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        PathPlayer { path, looping: false, time: None }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.time.is_some()
+    }
+
+    pub fn play(&mut self) {
+        if !self.path.keyframes().is_empty() {
+            self.time = Some(0.);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.time = None;
+    }
+
+    pub fn toggle_looping(&mut self) {
+        self.looping = !self.looping;
+        println!("Camera path looping {}", if self.looping { "on" } else { "off" });
+    }
+
+    /// Pose of the camera after dt seconds more, None once playback is over
+    pub fn advance(&mut self, dt: f32) -> Option<Pose> {
+        let duration = self.path.end() - self.path.start();
+        let mut time = self.time? + dt;
+        if time > duration {
+            if !self.looping {
+                self.time = None;
+                return None;
+            }
+            time = if duration > 0. { time % duration } else { 0. };
+        }
+        self.time = Some(time);
+        self.path.pose(self.path.start() + time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Point3};
+    use cgmath::prelude::*;
+    use rstest::*;
+
+    use crate::camera::Pose;
+    use crate::camera_path::{CameraPath, Keyframe, KeyframePosition, PathPlayer};
+
+    fn keyframe(time: f32, x: f32) -> Keyframe {
+        Keyframe { time, position: KeyframePosition::Cartesian([x, 1., 10.]), look_at: [0., 0., 0.], fov: 45. }
+    }
+
+    fn path() -> CameraPath {
+        CameraPath::new(vec![keyframe(1., 0.), keyframe(2., 2.), keyframe(4., 6.), keyframe(5., 4.)]).unwrap()
+    }
+
+    #[rstest(time, expected_x,
+    case(0., 0.),
+    case(1., 0.),
+    case(2., 2.),
+    case(4., 6.),
+    case(7., 4.),
+    )]
+    fn goes_through_keyframes(time: f32, expected_x: f32) {
+        let pose = path().pose(time).unwrap();
+        assert!((pose.eye - Point3::new(expected_x, 1., 10.)).magnitude() < 1e-5, "{:?}", pose.eye);
+    }
+
+    #[test]
+    fn moves_smoothly_through_keyframes() {
+        let path = path();
+        let velocity = |time: f32| (path.pose(time + 1e-3).unwrap().eye - path.pose(time - 1e-3).unwrap().eye) / 2e-3;
+        for time in &[2., 4.] {
+            let (before, after) = (velocity(time - 2e-3), velocity(time + 2e-3));
+            assert!((before - after).magnitude() < 0.05, "{:?} vs {:?} at {}", before, after, time);
+        }
+    }
+
+    #[test]
+    fn rejects_keyframes_out_of_order() {
+        assert!(CameraPath::new(vec![keyframe(1., 0.), keyframe(1., 2.)]).is_err());
+    }
+
+    #[test]
+    fn captured_keyframe_keeps_pose() {
+        let pose = Pose { eye: Point3::new(3., 4., 5.), look_at: Point3::new(0., 1., 0.), fov: Deg(30.) };
+        let mut path = CameraPath::default();
+        path.capture(&pose);
+        path.capture(&pose);
+        assert_eq!(path.keyframes()[1].time, 2.);
+        let captured = path.pose(0.).unwrap();
+        assert!((captured.eye - pose.eye).magnitude() < 1e-5, "{:?}", captured.eye);
+        assert_eq!(captured.fov, pose.fov);
+    }
+
+    #[test]
+    fn loops_playback() {
+        let mut player = PathPlayer::new(path());
+        player.play();
+        assert!(player.advance(5.).is_none());
+
+        player.looping = true;
+        player.play();
+        let pose = player.advance(5.).unwrap();
+        assert!((pose.eye.x - 2.).abs() < 1e-5, "{:?}", pose.eye);
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use cgmath::{Matrix4, vec3};
+use cgmath::prelude::*;
+use glfw::Window;
+
+use crate::offscreen::Framebuffer;
+use crate::xmas_tree::scene::Scene;
+
+/// Posters are rendered in square tiles of that many output pixels, so they can be larger than the largest framebuffer
+const POSTER_TILE_SIZE: u32 = 512;
+const SAMPLES: u32 = 4;
+
+/// Writes RGBA pixels, ordered from top to bottom, into a PNG file
+pub fn save_png<P: AsRef<Path>>(path: P, width: u32, height: u32, pixels: &[u8]) -> Result<(), png::EncodingError> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)
+}
+
+/// Name of a capture taken at given time in milliseconds, made unique with a counter when taken twice within a millisecond
+pub fn capture_path<F: Fn(&str) -> bool>(name: &str, timestamp_millis: u128, exists: F) -> String {
+    let path = format!("{}_{}.png", name, timestamp_millis);
+    if !exists(&path) {
+        return path;
+    }
+    (2..).map(|i| format!("{}_{}_{}.png", name, timestamp_millis, i)).find(|p| !exists(p)).unwrap()
+}
+
+/// Renders the scene as its camera sees it, at the window resolution multiplied by scale.
+/// Every output pixel is the average of supersampling x supersampling rendered ones.
+/// Returns width, height and RGBA pixels ordered from top to bottom.
+pub fn render_poster(scene: &mut Scene, window: &Window, scale: u32, supersampling: u32) -> (u32, u32, Vec<u8>) {
+    let (window_width, window_height) = window.get_framebuffer_size();
+    let (width, height) = (window_width as u32 * scale, window_height as u32 * scale);
+    let framebuffer = Framebuffer::new(POSTER_TILE_SIZE * supersampling, POSTER_TILE_SIZE * supersampling, SAMPLES);
+    let mut pixels = vec![0; (4 * width * height) as usize];
+
+    framebuffer.bind();
+    scene.camera.set_framebuffer_size(width, height);
+    for y in (0..height).step_by(POSTER_TILE_SIZE as usize) {
+        for x in (0..width).step_by(POSTER_TILE_SIZE as usize) {
+            scene.camera.set_crop(tile_crop(width, height, x, y, POSTER_TILE_SIZE));
+            scene.draw();
+            let tile = downsample(&framebuffer.read_pixels(), framebuffer.width, framebuffer.height, supersampling);
+            // tiles at the right and bottom edges stick out of the picture
+            let tile_width = POSTER_TILE_SIZE.min(width - x) as usize;
+            for row in 0..POSTER_TILE_SIZE.min(height - y) as usize {
+                let source = 4 * row * POSTER_TILE_SIZE as usize;
+                let target = 4 * ((y as usize + row) * width as usize + x as usize);
+                pixels[target..target + 4 * tile_width].copy_from_slice(&tile[source..source + 4 * tile_width]);
+            }
+        }
+    }
+    framebuffer.unbind();
+    unsafe { gl::Viewport(0, 0, window_width, window_height) }
+    scene.camera.set_crop(Matrix4::identity());
+    scene.camera.on_window_resize(window);
+    (width, height, pixels)
+}
+
+/// Transformation applied after projection, so that only the square of given size and top left corner,
+/// in pixels of the whole picture, fills the viewport
+pub fn tile_crop(width: u32, height: u32, x: u32, y: u32, size: u32) -> Matrix4<f32> {
+    let (width, height, size) = (width as f32, height as f32, size as f32);
+    let (scale_x, scale_y) = (width / size, height / size);
+    // tile centre in normalized device coordinates, y goes up there
+    let center_x = 2. * (x as f32 + size / 2.) / width - 1.;
+    let center_y = 1. - 2. * (y as f32 + size / 2.) / height;
+    Matrix4::from_nonuniform_scale(scale_x, scale_y, 1.) * Matrix4::from_translation(vec3(-center_x, -center_y, 0.))
+}
+
+/// Averages every factor x factor block of RGBA pixels into one, dimensions have to be multiples of factor
+pub fn downsample(pixels: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    let (width, factor) = (width as usize, factor as usize);
+    let (small_width, small_height) = (width / factor, height as usize / factor);
+    let mut small = Vec::with_capacity(4 * small_width * small_height);
+    for y in 0..small_height {
+        for x in 0..small_width {
+            for channel in 0..4 {
+                let sum: u32 = (0..factor * factor)
+                    .map(|i| pixels[4 * ((y * factor + i / factor) * width + x * factor + i % factor) + channel] as u32)
+                    .sum();
+                small.push(((sum + (factor * factor / 2) as u32) / (factor * factor) as u32) as u8);
+            }
+        }
+    }
+    small
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, vec4};
+    use cgmath::prelude::*;
+
+    use crate::capture::{capture_path, downsample, tile_crop};
+
+    #[test]
+    fn averages_blocks_of_pixels() {
+        let pixels = [
+            0, 0, 0, 255, 10, 20, 30, 255, 100, 100, 100, 255, 100, 100, 100, 255,
+            20, 40, 60, 255, 30, 60, 90, 255, 100, 100, 100, 255, 100, 100, 100, 255,
+        ];
+        assert_eq!(downsample(&pixels, 4, 2, 2), vec![15, 30, 45, 255, 100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn numbers_captures_taken_at_the_same_time() {
+        assert_eq!(capture_path("screenshot", 1608854400123, |_| false), "screenshot_1608854400123.png");
+        let taken = ["poster_1608854400123.png", "poster_1608854400123_2.png"];
+        assert_eq!(capture_path("poster", 1608854400123, |p| taken.contains(&p)), "poster_1608854400123_3.png");
+    }
+
+    #[test]
+    fn cropped_tile_fills_viewport() {
+        // tile in the bottom half of a 200x100 picture, right of its centre
+        let crop = tile_crop(200, 100, 100, 50, 50);
+        let corner = |x: f32, y: f32| {
+            let p = crop * vec4(x, y, 0., 1.);
+            Point3::new(p.x, p.y, p.z)
+        };
+        assert!((corner(0., 0.) - Point3::new(-1., 1., 0.)).magnitude() < 1e-5, "{:?}", corner(0., 0.));
+        assert!((corner(0.5, -1.) - Point3::new(1., -1., 0.)).magnitude() < 1e-5, "{:?}", corner(0.5, -1.));
+    }
+}
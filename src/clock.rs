@@ -0,0 +1,152 @@
+use std::time::Instant;
+
+const MIN_TIME_SCALE: f32 = 1. / 16.;
+const MAX_TIME_SCALE: f32 = 16.;
+/// With fixed timestep the simulation never does more steps per frame, otherwise slow frames would make next ones even slower
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+/// Measures real time between frames and turns it into simulation time steps.
+/// Simulation time can be paused, slowed down or sped up. With fixed timestep it always advances in steps of the same size,
+/// leftover time is accumulated and used in next frames.
+pub struct Clock {
+    last_tick: Instant,
+    /// real time the last frame took, not affected by pause or time scale
+    frame_time: f32,
+    accumulator: f32,
+    pub paused: bool,
+    pub time_scale: f32,
+    pub fixed_timestep: Option<f32>,
+}
+
+/// Simulation time steps to be done in a single frame, in seconds
+#[derive(Debug, PartialEq)]
+pub struct Steps {
+    dt: f32,
+    count: u32,
+}
+
+impl Iterator for Steps {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.count == 0 {
+            None
+        } else {
+            self.count -= 1;
+            Some(self.dt)
+        }
+    }
+}
+
+impl Clock {
+    pub fn new(fixed_timestep: Option<f32>) -> Self {
+        Clock { last_tick: Instant::now(), frame_time: 0., accumulator: 0., paused: false, time_scale: 1., fixed_timestep }
+    }
+
+    /// Returns simulation steps to be done for the time elapsed since the previous tick
+    pub fn tick(&mut self) -> Steps {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick).as_secs_f32();
+        self.last_tick = now;
+        self.frame_time = elapsed;
+        self.advance(elapsed)
+    }
+
+    pub fn frame_time(&self) -> f32 {
+        self.frame_time
+    }
+
+    fn advance(&mut self, elapsed: f32) -> Steps {
+        if self.paused {
+            return Steps { dt: 0., count: 0 };
+        }
+        let elapsed = elapsed * self.time_scale;
+        match self.fixed_timestep {
+            None => Steps { dt: elapsed, count: 1 },
+            Some(dt) => {
+                self.accumulator += elapsed;
+                let count = (self.accumulator / dt) as u32;
+                if count > MAX_STEPS_PER_FRAME {
+                    self.accumulator = 0.;
+                    Steps { dt, count: MAX_STEPS_PER_FRAME }
+                } else {
+                    self.accumulator -= count as f32 * dt;
+                    Steps { dt, count }
+                }
+            }
+        }
+    }
+
+    /// Simulation step for a recorded frame lasting frame_dt, recordings are paused and slowed down together with the clock
+    pub fn recording_step(&self, frame_dt: f32) -> Option<f32> {
+        if self.paused {
+            None
+        } else {
+            Some(frame_dt * self.time_scale)
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn speed_up(&mut self) {
+        self.time_scale = (self.time_scale * 2.).min(MAX_TIME_SCALE);
+    }
+
+    pub fn slow_down(&mut self) {
+        self.time_scale = (self.time_scale / 2.).max(MIN_TIME_SCALE);
+    }
+
+    pub fn reset_time_scale(&mut self) {
+        self.time_scale = 1.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use crate::clock::{Clock, Steps};
+
+    #[rstest(fixed_timestep, time_scale, elapsed, expected,
+    case(None, 1., 0.016, Steps { dt: 0.016, count: 1 }),
+    case(None, 0.5, 0.016, Steps { dt: 0.008, count: 1 }),
+    case(None, 2., 0.016, Steps { dt: 0.032, count: 1 }),
+    case(Some(0.01), 1., 0.035, Steps { dt: 0.01, count: 3 }),
+    case(Some(0.01), 1., 0.005, Steps { dt: 0.01, count: 0 }),
+    case(Some(0.01), 2., 0.036, Steps { dt: 0.01, count: 7 }),
+    case(Some(0.01), 1., 10., Steps { dt: 0.01, count: 8 }),
+    )]
+    fn advances_by_elapsed_time(fixed_timestep: Option<f32>, time_scale: f32, elapsed: f32, expected: Steps) {
+        let mut clock = Clock::new(fixed_timestep);
+        clock.time_scale = time_scale;
+        assert_eq!(clock.advance(elapsed), expected);
+    }
+
+    #[test]
+    fn accumulates_leftover_time() {
+        let mut clock = Clock::new(Some(0.01));
+        assert_eq!(clock.advance(0.006).count(), 0);
+        assert_eq!(clock.advance(0.006).count(), 1);
+        assert_eq!(clock.advance(0.009).count(), 1);
+    }
+
+    #[test]
+    fn does_not_advance_when_paused() {
+        let mut clock = Clock::new(None);
+        clock.toggle_pause();
+        assert_eq!(clock.advance(0.016).count(), 0);
+        clock.toggle_pause();
+        assert_eq!(clock.advance(0.016).count(), 1);
+    }
+
+    #[test]
+    fn recording_follows_pause_and_time_scale() {
+        let mut clock = Clock::new(None);
+        clock.slow_down();
+        assert_eq!(clock.recording_step(0.04), Some(0.02));
+        clock.toggle_pause();
+        assert_eq!(clock.recording_step(0.04), None);
+    }
+}
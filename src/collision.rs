@@ -0,0 +1,78 @@
+use cgmath::{Point3, vec2};
+use cgmath::prelude::*;
+
+/// Simple shapes falling snow bumps into
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Collider {
+    /// snowflakes slide off it
+    Sphere { center: Point3<f32>, radius: f32 },
+    /// vertical truncated cone standing on its bottom center, snowflakes hitting it stay there
+    Frustum { bottom: Point3<f32>, height: f32, bottom_radius: f32, top_radius: f32 },
+}
+
+impl Collider {
+    /// Stack of frusta around a vertical axis enclosing all the points, the axis goes through the middle of their bounding box.
+    /// Radius at the border of two sections is the largest distance from the axis in both of them.
+    pub fn envelope(points: &[Point3<f32>], sections: usize) -> Vec<Collider> {
+        if points.is_empty() || sections == 0 {
+            return vec![];
+        }
+        let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+            (Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)), Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)))
+        });
+        let axis = vec2((min.x + max.x) / 2., (min.z + max.z) / 2.);
+        let height = (max.y - min.y) / sections as f32;
+        let mut section_radii = vec![0_f32; sections];
+        for p in points {
+            let section = if height > 0. { (((p.y - min.y) / height) as usize).min(sections - 1) } else { 0 };
+            section_radii[section] = section_radii[section].max((vec2(p.x, p.z) - axis).magnitude());
+        }
+        // borders between sections touch two of them, the bottom and the top one only one
+        let border_radius = |border: usize| section_radii[border.saturating_sub(1)].max(section_radii[border.min(sections - 1)]);
+        (0..sections)
+            .map(|i| Collider::Frustum {
+                bottom: Point3::new(axis.x, min.y + i as f32 * height, axis.y),
+                height,
+                bottom_radius: border_radius(i),
+                top_radius: border_radius(i + 1),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, vec2};
+    use cgmath::prelude::*;
+
+    use crate::collision::Collider;
+
+    #[test]
+    fn envelope_encloses_all_points() {
+        let points: Vec<Point3<f32>> = (0..50)
+            .map(|i| {
+                let y = i as f32 / 10.;
+                let r = (5. - y) * (1. + 0.3 * (i as f32).sin());
+                Point3::new(1. + r * (i as f32).cos(), y, -2. + r * (i as f32).sin())
+            })
+            .collect();
+        let envelope = Collider::envelope(&points, 4);
+        assert_eq!(envelope.len(), 4);
+        for p in &points {
+            let enclosed = envelope.iter().any(|collider| match *collider {
+                Collider::Frustum { bottom, height, bottom_radius, top_radius } => {
+                    let t = (p.y - bottom.y) / height;
+                    let radius = bottom_radius + (top_radius - bottom_radius) * t;
+                    (0. ..=1.).contains(&t) && (vec2(p.x, p.z) - vec2(bottom.x, bottom.z)).magnitude() <= radius + 1e-4
+                }
+                Collider::Sphere { .. } => false,
+            });
+            assert!(enclosed, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn envelope_of_nothing_is_empty() {
+        assert!(Collider::envelope(&[], 4).is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+/// Glyphs are 5 pixels wide and 7 high, every row is a byte with the leftmost pixel in bit 4
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+/// Glyphs are placed next to each other in the atlas with a column of empty pixels between them
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+
+/// Fully lit glyph, used to draw solid rectangles with the same texture as text
+pub const SOLID: char = '\u{2588}';
+const UNKNOWN: char = '?';
+
+const GLYPHS: &[(char, [u8; 7])] = &[
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    (SOLID, [0x1F, 0x1F, 0x1F, 0x1F, 0x1F, 0x1F, 0x1F]),
+];
+
+/// Single channel texture with all glyphs in a row, 255 where a glyph is lit
+pub struct Atlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Atlas {
+    pub fn new() -> Self {
+        let (width, height) = (GLYPHS.len() as u32 * CELL_WIDTH, GLYPH_HEIGHT);
+        let mut pixels = vec![0; (width * height) as usize];
+        for (i, (_, rows)) in GLYPHS.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                        pixels[y * width as usize + (i as u32 * CELL_WIDTH + x) as usize] = 255;
+                    }
+                }
+            }
+        }
+        Atlas { width, height, pixels }
+    }
+
+    /// Texture coordinates of the glyph: left, top, right and bottom, with the first row of pixels at the top.
+    /// Lowercase letters are shown as uppercase ones, characters without a glyph as a question mark.
+    pub fn glyph(&self, c: char) -> [f32; 4] {
+        let c = c.to_ascii_uppercase();
+        let index = GLYPHS.iter().position(|(g, _)| *g == c)
+            .unwrap_or_else(|| GLYPHS.iter().position(|(g, _)| *g == UNKNOWN).unwrap());
+        let left = (index as u32 * CELL_WIDTH) as f32 / self.width as f32;
+        let right = (index as u32 * CELL_WIDTH + GLYPH_WIDTH) as f32 / self.width as f32;
+        [left, 0., right, 1.]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::font::{Atlas, GLYPH_HEIGHT};
+
+    #[test]
+    fn draws_glyphs_into_atlas() {
+        let atlas = Atlas::new();
+        let [left, _, right, _] = atlas.glyph('T');
+        let (left, right) = ((left * atlas.width as f32).round() as usize, (right * atlas.width as f32).round() as usize);
+        let top_row = &atlas.pixels[left..right];
+        assert_eq!(top_row, &[255, 255, 255, 255, 255]);
+        let bottom_row = &atlas.pixels[(GLYPH_HEIGHT as usize - 1) * atlas.width as usize..][left..right];
+        assert_eq!(bottom_row, &[0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn falls_back_to_known_glyphs() {
+        let atlas = Atlas::new();
+        assert_eq!(atlas.glyph('a'), atlas.glyph('A'));
+        assert_eq!(atlas.glyph('#'), atlas.glyph('?'));
+    }
+}
@@ -5,15 +5,17 @@ use crate::observer::RenderLoopObserver;
 
 const FPS_ARRAY_SIZE: usize = 100;
 
+/// Keeps times of the last frames, shown by the HUD
 pub struct FpsCalculator {
     frame_times: VecDeque<Instant>,
+    fps: f64,
 }
 
 impl RenderLoopObserver for FpsCalculator {
     fn new() -> Self {
         let mut frame_times: VecDeque<Instant> = VecDeque::with_capacity(FPS_ARRAY_SIZE);
         frame_times.push_back(Instant::now());
-        FpsCalculator { frame_times }
+        FpsCalculator { frame_times, fps: 0. }
     }
 
     fn tick(&mut self) {
@@ -23,8 +25,21 @@ impl RenderLoopObserver for FpsCalculator {
             *(self.frame_times.front().unwrap())
         };
         let elapsed = earliest_frame.elapsed();
-        let fps = 1000000.0 * self.frame_times.len() as f64 / elapsed.as_micros() as f64;
-        println!("FPS: {:?}, elapsed: {:?}", fps, elapsed);
+        self.fps = 1000000.0 * self.frame_times.len() as f64 / elapsed.as_micros() as f64;
         self.frame_times.push_back(Instant::now());
     }
 }
+
+impl FpsCalculator {
+    /// Average over the last frames
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    /// Durations of the last frames in seconds, from the oldest one
+    pub fn frame_times(&self) -> Vec<f32> {
+        self.frame_times.iter().zip(self.frame_times.iter().skip(1))
+            .map(|(earlier, later)| later.duration_since(*earlier).as_secs_f32())
+            .collect()
+    }
+}
@@ -0,0 +1,92 @@
+use cgmath::Point3;
+
+use crate::overlay::{char_width, Colour, line_height, Overlay};
+
+/// Size of a font pixel in window pixels
+const TEXT_SCALE: f32 = 2.;
+const MARGIN: f32 = 10.;
+const GRAPH_HEIGHT: f32 = 60.;
+const GRAPH_BAR_WIDTH: f32 = 3.;
+/// Frame times this long fill the whole height of the graph
+const GRAPH_MAX_FRAME_TIME: f32 = 1. / 20.;
+
+const TEXT_COLOUR: Colour = [1., 1., 1., 1.];
+const BACKGROUND_COLOUR: Colour = [0., 0., 0., 0.5];
+
+pub struct HudStats {
+    pub fps: f64,
+    /// in seconds, from the oldest frame
+    pub frame_times: Vec<f32>,
+    pub snowflakes: usize,
+    pub draw_calls: usize,
+    pub camera: Point3<f32>,
+}
+
+/// Statistics drawn in the top left corner of the window
+pub struct Hud {
+    pub enabled: bool,
+    overlay: Overlay,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud { enabled: false, overlay: Overlay::new() }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Draws over the scene in the framebuffer of given size
+    pub fn draw(&mut self, stats: &HudStats, width: u32, height: u32) {
+        if !self.enabled {
+            return;
+        }
+        let text = describe(stats);
+        let lines = text.lines().count() as f32;
+        let longest = text.lines().map(|l| l.len()).max().unwrap_or(0) as f32;
+        let text_width = longest * char_width(TEXT_SCALE);
+        let graph_width = stats.frame_times.len() as f32 * GRAPH_BAR_WIDTH;
+        let panel_width = text_width.max(graph_width) + 2. * MARGIN;
+        let graph_top = 2. * MARGIN + lines * line_height(TEXT_SCALE);
+        self.overlay.rect(0., 0., panel_width, graph_top + GRAPH_HEIGHT + MARGIN, BACKGROUND_COLOUR);
+        self.overlay.text(&text, MARGIN, MARGIN, TEXT_SCALE, TEXT_COLOUR);
+
+        for (i, frame_time) in stats.frame_times.iter().enumerate() {
+            let bar_height = (frame_time / GRAPH_MAX_FRAME_TIME).min(1.) * GRAPH_HEIGHT;
+            let x = MARGIN + i as f32 * GRAPH_BAR_WIDTH;
+            self.overlay.rect(x, graph_top + GRAPH_HEIGHT - bar_height, GRAPH_BAR_WIDTH - 1., bar_height, bar_colour(*frame_time));
+        }
+        self.overlay.draw(width, height);
+    }
+}
+
+fn describe(stats: &HudStats) -> String {
+    let last_frame = stats.frame_times.last().copied().unwrap_or(0.);
+    format!("FPS {:.1}\nFRAME {:.1} MS\nSNOWFLAKES {}\nDRAW CALLS {}\nCAMERA {:.1} {:.1} {:.1}",
+            stats.fps, 1000. * last_frame, stats.snowflakes, stats.draw_calls, stats.camera.x, stats.camera.y, stats.camera.z)
+}
+
+/// Green for frames fitting in 60 fps, yellow for 30 fps and red for slower ones
+fn bar_colour(frame_time: f32) -> Colour {
+    if frame_time <= 1. / 60. {
+        [0.2, 0.9, 0.2, 1.]
+    } else if frame_time <= 1. / 30. {
+        [0.9, 0.9, 0.2, 1.]
+    } else {
+        [0.9, 0.2, 0.2, 1.]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use crate::hud::{describe, HudStats};
+
+    #[test]
+    fn describes_stats() {
+        let stats = HudStats { fps: 59.94, frame_times: vec![0.016, 0.02], snowflakes: 1000, draw_calls: 12, camera: Point3::new(1., -2.3, 3.) };
+        assert_eq!(describe(&stats), "FPS 59.9\nFRAME 20.0 MS\nSNOWFLAKES 1000\nDRAW CALLS 12\nCAMERA 1.0 -2.3 3.0");
+    }
+}
@@ -0,0 +1,144 @@
+use cgmath::{Matrix4, Point3, Vector4};
+use cgmath::prelude::*;
+
+use crate::lights::Light;
+
+/// Size of a square screen tile in pixels
+pub const TILE_SIZE: u32 = 16;
+/// Corners of the light's bounding box closer to the camera than that make the light cover the whole screen
+const MIN_DEPTH: f32 = 0.01;
+
+/// Screen divided into tiles, every tile knows which lights can reach any of its pixels.
+/// Fragment shader goes only through the lights of its own tile, so lots of small lights cost only where they shine.
+#[derive(Debug)]
+pub struct LightGrid {
+    pub tiles_x: u32,
+    /// offset into indices and number of lights for every tile, row by row starting at the bottom left
+    pub tiles: Vec<[u32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// Tiles covered by a light, both ends inclusive
+#[derive(Debug, PartialEq)]
+struct TileRect {
+    min_x: u32,
+    min_y: u32,
+    max_x: u32,
+    max_y: u32,
+}
+
+impl LightGrid {
+    /// Empty viewport, e.g. of a minimised window, has no tiles at all
+    pub fn build(lights: &[Light], view: &Matrix4<f32>, projection: &Matrix4<f32>, width: u32, height: u32) -> Self {
+        if width == 0 || height == 0 {
+            return LightGrid { tiles_x: 0, tiles: vec![], indices: vec![] };
+        }
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        let mut tile_lights: Vec<Vec<u32>> = vec![vec![]; (tiles_x * tiles_y) as usize];
+        for (i, light) in lights.iter().enumerate() {
+            if let Some(rect) = Self::covered_tiles(light, view, projection, width, height) {
+                for y in rect.min_y..=rect.max_y {
+                    for x in rect.min_x..=rect.max_x {
+                        tile_lights[(y * tiles_x + x) as usize].push(i as u32);
+                    }
+                }
+            }
+        }
+
+        let mut tiles = Vec::with_capacity(tile_lights.len());
+        let mut indices = vec![];
+        for tile in tile_lights {
+            tiles.push([indices.len() as u32, tile.len() as u32]);
+            indices.extend(tile);
+        }
+        LightGrid { tiles_x, tiles, indices }
+    }
+
+    /// Projects the bounding box of the light's sphere onto the screen, None when the light is not visible at all.
+    /// Lights without range or position reach every tile. The viewport must not be empty.
+    fn covered_tiles(light: &Light, view: &Matrix4<f32>, projection: &Matrix4<f32>, width: u32, height: u32) -> Option<TileRect> {
+        let all = TileRect { min_x: 0, min_y: 0, max_x: (width - 1) / TILE_SIZE, max_y: (height - 1) / TILE_SIZE };
+        let (position, range) = match (light.position(), light.range) {
+            (Some(position), Some(range)) => (position, range),
+            _ => return Some(all),
+        };
+        let center = view.transform_point(position);
+        if center.z - range > 0. {
+            return None; // behind the camera
+        }
+
+        let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+        for corner in 0..8 {
+            let offset = |bit: i32| if corner & bit == 0 { -range } else { range };
+            let corner = Point3::new(center.x + offset(1), center.y + offset(2), center.z + offset(4));
+            if corner.z > -MIN_DEPTH {
+                return Some(all);
+            }
+            let clip = projection * Vector4::new(corner.x, corner.y, corner.z, 1.);
+            for axis in 0..2 {
+                let ndc = clip[axis] / clip.w;
+                min[axis] = min[axis].min(ndc);
+                max[axis] = max[axis].max(ndc);
+            }
+        }
+        if max[0] < -1. || min[0] > 1. || max[1] < -1. || min[1] > 1. {
+            return None;
+        }
+
+        let to_tile = |ndc: f32, size: u32| {
+            let pixel = (ndc.clamp(-1., 1.) + 1.) / 2. * size as f32;
+            (pixel as u32).min(size - 1) / TILE_SIZE
+        };
+        Some(TileRect { min_x: to_tile(min[0], width), min_y: to_tile(min[1], height), max_x: to_tile(max[0], width), max_y: to_tile(max[1], height) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, perspective, Point3, vec3};
+    use rstest::*;
+
+    use crate::light_grid::{LightGrid, TileRect};
+    use crate::lights::{Attenuation, Light, LightKind};
+
+    fn light(position: Point3<f32>, range: Option<f32>) -> Light {
+        let kind = LightKind::Point { position, attenuation: Attenuation::NONE };
+        Light { kind, ambient: vec3(0., 0., 0.), diffuse: vec3(1., 1., 1.), specular: vec3(0., 0., 0.), range }
+    }
+
+    #[rstest(position, range, expected,
+    case(Point3::new(0., 0., -10.), None, Some(TileRect { min_x: 0, min_y: 0, max_x: 7, max_y: 3 })),
+    case(Point3::new(0., 0., -10.), Some(0.5), Some(TileRect { min_x: 3, min_y: 1, max_x: 4, max_y: 2 })),
+    case(Point3::new(0., 0., 10.), Some(0.5), None),
+    case(Point3::new(100., 0., -10.), Some(0.5), None),
+    case(Point3::new(0., 0., -0.2), Some(0.5), Some(TileRect { min_x: 0, min_y: 0, max_x: 7, max_y: 3 })),
+    )]
+    fn finds_tiles_covered_by_light(position: Point3<f32>, range: Option<f32>, expected: Option<TileRect>) {
+        let view = Matrix4::look_at(Point3::new(0., 0., 0.), Point3::new(0., 0., -1.), vec3(0., 1., 0.));
+        let projection = perspective(Deg(90.), 2., 0.1, 100.);
+        assert_eq!(LightGrid::covered_tiles(&light(position, range), &view, &projection, 128, 64), expected);
+    }
+
+    #[test]
+    fn lists_lights_of_every_tile() {
+        let view = Matrix4::look_at(Point3::new(0., 0., 0.), Point3::new(0., 0., -1.), vec3(0., 1., 0.));
+        let projection = perspective(Deg(90.), 1., 0.1, 100.);
+        let lights = [light(Point3::new(0., 0., 0.), None), light(Point3::new(-9., -9., -10.), Some(0.5))];
+        let grid = LightGrid::build(&lights, &view, &projection, 32, 32);
+        assert_eq!(grid.tiles_x, 2);
+        assert_eq!(grid.tiles, vec![[0, 2], [2, 1], [3, 1], [4, 1]]);
+        assert_eq!(grid.indices, vec![0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn empty_viewport_has_no_tiles() {
+        let view = Matrix4::look_at(Point3::new(0., 0., 0.), Point3::new(0., 0., -1.), vec3(0., 1., 0.));
+        let projection = perspective(Deg(90.), 1., 0.1, 100.);
+        let lights = [light(Point3::new(0., 0., 0.), None), light(Point3::new(0., 0., -10.), Some(0.5))];
+        let grid = LightGrid::build(&lights, &view, &projection, 0, 0);
+        assert_eq!(grid.tiles_x, 0);
+        assert!(grid.tiles.is_empty());
+        assert!(grid.indices.is_empty());
+    }
+}
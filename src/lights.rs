@@ -1,63 +1,327 @@
-#![allow(dead_code)]
 extern crate gl;
 
-use std::{mem, ptr};
-use std::os::raw::c_void;
+use std::mem;
 
-use cgmath::{Point3, Vector3, Vector4};
+use cgmath::{Deg, Matrix3, Matrix4, Point3, Rad, Vector3};
 use cgmath::prelude::*;
+use serde::Deserialize;
 
-use crate::shader::LIGHTS_UBO_BINDING_POINT;
+use crate::light_grid::{LightGrid, TILE_SIZE};
+use crate::shader::Shader;
+use crate::texture_buffer::TextureBuffer;
 
-use self::gl::types::*;
+/// Texture units the light buffers are bound to while drawing the scene, 0 is taken by the shadow map
+pub const LIGHTS_TEXTURE_UNIT: u32 = 1;
+pub const LIGHT_TILES_TEXTURE_UNIT: u32 = 2;
+pub const LIGHT_INDICES_TEXTURE_UNIT: u32 = 3;
+/// Number of lights the buffers have room for at the start, they grow when needed
+const INITIAL_LIGHTS_CAPACITY: usize = 64;
+/// Number of RGBA texels a single light takes in the lights buffer
+const LIGHT_TEXELS: usize = 6;
 
-const MAX_LIGHTS: isize = 4;
+/// Light gets weaker with distance d as 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation { constant: 1., linear: 0., quadratic: 0. };
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Attenuation::NONE
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far light shining the same everywhere, like the sun or the moon
+    Directional { direction: Vector3<f32> },
+    /// Shines in all directions from its position
+    Point { position: Point3<f32>, attenuation: Attenuation },
+    /// Point light limited to a cone, angles are measured between the cone axis and its edge.
+    /// The light is full inside the inner angle and fades out towards the outer one.
+    Spot { position: Point3<f32>, direction: Vector3<f32>, inner_angle: Deg<f32>, outer_angle: Deg<f32>, attenuation: Attenuation },
+}
+
+/// A light with range fades out smoothly and doesn't reach further than the range,
+/// a light without it is limited only by its attenuation
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+    pub range: Option<f32>,
+}
+
+impl Light {
+    /// Position of the light, directional lights have none
+    pub fn position(&self) -> Option<Point3<f32>> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+
+    /// The same light turned around the Y axis going through the origin
+    pub fn turned(&self, angle: Rad<f32>) -> Light {
+        let rotation = Matrix3::from_angle_y(angle);
+        let kind = match self.kind {
+            LightKind::Directional { direction } => LightKind::Directional { direction: rotation * direction },
+            LightKind::Point { position, attenuation } =>
+                LightKind::Point { position: Point3::from_vec(rotation * position.to_vec()), attenuation },
+            LightKind::Spot { position, direction, inner_angle, outer_angle, attenuation } => LightKind::Spot {
+                position: Point3::from_vec(rotation * position.to_vec()),
+                direction: rotation * direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            },
+        };
+        Light { kind, ..*self }
+    }
+
+    /// Layout of a light in the lights buffer, has to match fetchLight() in static.frag
+    fn texels(&self) -> [[f32; 4]; LIGHT_TEXELS] {
+        let (kind, position, direction, cutoffs, attenuation) = match self.kind {
+            LightKind::Directional { direction } =>
+                (0., Point3::origin(), direction.normalize(), (0., 0.), Attenuation::NONE),
+            LightKind::Point { position, attenuation } =>
+                (1., position, Vector3::zero(), (0., 0.), attenuation),
+            LightKind::Spot { position, direction, inner_angle, outer_angle, attenuation } =>
+                (2., position, direction.normalize(), (inner_angle.cos(), outer_angle.cos()), attenuation),
+        };
+        [
+            [position.x, position.y, position.z, kind],
+            [direction.x, direction.y, direction.z, self.range.unwrap_or(0.)],
+            [self.ambient.x, self.ambient.y, self.ambient.z, cutoffs.0],
+            [self.diffuse.x, self.diffuse.y, self.diffuse.z, cutoffs.1],
+            [self.specular.x, self.specular.y, self.specular.z, 0.],
+            [attenuation.constant, attenuation.linear, attenuation.quadratic, 0.],
+        ]
+    }
+}
+
+/// Handle of a light added with Lights::add(), stays valid until the light is removed.
+/// Slots of removed lights are reused, the generation tells the old light's handle from the new one's.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId {
+    index: usize,
+    generation: u32,
+}
+
+struct StaticLight {
+    light: Light,
+    enabled: bool,
+}
 
-struct Light {
-    position: Point3<f32>,
-    ambient: Vector3<f32>,
-    diffuse: Vector3<f32>,
-    specular: Vector3<f32>,
+struct Slot {
+    generation: u32,
+    light: Option<StaticLight>,
 }
 
+/// Static lights reachable by their handles, a removed light leaves a free slot for the next added one
+#[derive(Default)]
+struct Slots {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+impl Slots {
+    fn add(&mut self, light: Light) -> LightId {
+        let light = Some(StaticLight { light, enabled: true });
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.generation += 1;
+                slot.light = light;
+                LightId { index, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, light });
+                LightId { index: self.slots.len() - 1, generation: 0 }
+            }
+        }
+    }
+
+    fn get(&self, id: LightId) -> Option<&StaticLight> {
+        self.slots.get(id.index).filter(|s| s.generation == id.generation).and_then(|s| s.light.as_ref())
+    }
+
+    fn get_mut(&mut self, id: LightId) -> Option<&mut StaticLight> {
+        self.slots.get_mut(id.index).filter(|s| s.generation == id.generation).and_then(|s| s.light.as_mut())
+    }
+
+    /// Returns false when the light has already been removed
+    fn remove(&mut self, id: LightId) -> bool {
+        match self.slots.get_mut(id.index) {
+            Some(slot) if slot.generation == id.generation && slot.light.is_some() => {
+                slot.light = None;
+                self.free.push(id.index);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Lights are either static, added with add() and changed only on request, or dynamic, coming from models and replaced every frame.
+/// Enabled static lights always come first in the lights buffer, dynamic ones follow.
+/// Before drawing the lights are sorted into screen tiles, see LightGrid.
 pub struct Lights {
-    ubo: u32,
-    lights: Vec<Light>,
+    slots: Slots,
+    dynamic_lights: Vec<Light>,
+    /// lights in the order they're in the lights buffer
+    uploaded: Vec<Light>,
+    /// index in the lights buffer of every slot, None for removed and disabled lights
+    buffer_indices: Vec<Option<usize>>,
+    lights_buffer: TextureBuffer,
+    tiles_buffer: TextureBuffer,
+    indices_buffer: TextureBuffer,
 }
 
 impl Lights {
     pub fn setup() -> Self {
-        Lights { ubo: Lights::setup_lights_ubo(), lights: vec![] }
+        let light_size = (LIGHT_TEXELS * mem::size_of::<[f32; 4]>()) as isize;
+        let index_size = mem::size_of::<u32>() as isize;
+        Lights {
+            slots: Slots::default(),
+            dynamic_lights: vec![],
+            uploaded: vec![],
+            buffer_indices: vec![],
+            lights_buffer: TextureBuffer::new(gl::RGBA32F, INITIAL_LIGHTS_CAPACITY as isize * light_size),
+            tiles_buffer: TextureBuffer::new(gl::RG32UI, 1024 * 2 * index_size),
+            indices_buffer: TextureBuffer::new(gl::R32UI, 4 * INITIAL_LIGHTS_CAPACITY as isize * index_size),
+        }
     }
 
-    fn setup_lights_ubo() -> u32 {
-        unsafe {
-            let mut lights_ubo = 0 as u32;
-            gl::GenBuffers(1, &mut lights_ubo);
-            gl::BindBuffer(gl::UNIFORM_BUFFER, lights_ubo);
-            let vector3_size = mem::size_of::<Vector4<f32>>() as isize; // there's no mistake, Vector3 takes the same amount of memory as Vector4
-            gl::BufferData(gl::UNIFORM_BUFFER, 16 + MAX_LIGHTS * 4 * vector3_size, ptr::null(), gl::STATIC_DRAW);
-            gl::BindBufferBase(gl::UNIFORM_BUFFER, LIGHTS_UBO_BINDING_POINT, lights_ubo);
-            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
-            lights_ubo
+    pub fn add(&mut self, light: Light) -> LightId {
+        let id = self.slots.add(light);
+        self.upload_lights();
+        id
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id).map(|s| &s.light)
+    }
+
+    /// Replaces the light, does nothing when it has been removed
+    pub fn update(&mut self, id: LightId, light: Light) {
+        if let Some(slot) = self.slots.get_mut(id) {
+            slot.light = light;
+            self.upload_lights();
         }
     }
 
-    pub fn add(&mut self, position: Point3<f32>, ambient: Vector3<f32>, diffuse: Vector3<f32>, specular: Vector3<f32>) {
-        let light = Light {position, ambient, diffuse, specular};
-        self.lights.push(light);
-        let lights_no= self.lights.len() as isize;
+    pub fn is_enabled(&self, id: LightId) -> bool {
+        self.slots.get(id).is_some_and(|s| s.enabled)
+    }
+
+    /// Disabled lights are kept but don't light up anything
+    pub fn set_enabled(&mut self, id: LightId, enabled: bool) {
+        if let Some(slot) = self.slots.get_mut(id) {
+            slot.enabled = enabled;
+            self.upload_lights();
+        }
+    }
+
+    pub fn remove(&mut self, id: LightId) {
+        if self.slots.remove(id) {
+            self.upload_lights();
+        }
+    }
+
+    /// Index of the light in the lights buffer, None if it's disabled or removed
+    pub fn buffer_index(&self, id: LightId) -> Option<usize> {
+        self.slots.get(id).and(self.buffer_indices.get(id.index).copied().flatten())
+    }
+
+    /// Replaces all dynamic lights with given ones
+    pub fn set_dynamic(&mut self, dynamic_lights: &[Light]) {
+        self.dynamic_lights.clear();
+        self.dynamic_lights.extend_from_slice(dynamic_lights);
+        self.upload_lights();
+    }
+
+    fn upload_lights(&mut self) {
+        self.uploaded.clear();
+        self.buffer_indices.clear();
+        for slot in &self.slots.slots {
+            match &slot.light {
+                Some(slot) if slot.enabled => {
+                    self.buffer_indices.push(Some(self.uploaded.len()));
+                    self.uploaded.push(slot.light);
+                }
+                _ => self.buffer_indices.push(None),
+            }
+        }
+        self.uploaded.extend_from_slice(&self.dynamic_lights);
+
+        let texels: Vec<[[f32; 4]; LIGHT_TEXELS]> = self.uploaded.iter().map(|l| l.texels()).collect();
+        self.lights_buffer.upload(&texels);
+    }
+
+    /// Finds lights reaching every tile of the current viewport and makes them available to given shader
+    pub fn cull(&mut self, view: &Matrix4<f32>, projection: &Matrix4<f32>, shader: &Shader) {
+        let mut viewport = [0; 4];
         unsafe {
-            let int_size = mem::size_of::<GLint>() as isize;
-            let vector3_size = mem::size_of::<Vector4<f32>>() as isize;
-            let light_size = 4 * vector3_size;
-            gl::BindBuffer(gl::UNIFORM_BUFFER, self.ubo);
-            gl::BufferSubData(gl::UNIFORM_BUFFER, 0, int_size, &lights_no as *const isize as *const c_void);
-            gl::BufferSubData(gl::UNIFORM_BUFFER, 16 + (lights_no - 1) * light_size + 0 * vector3_size, vector3_size, position.as_ptr() as *const c_void);
-            gl::BufferSubData(gl::UNIFORM_BUFFER, 16 + (lights_no - 1) * light_size + 1 * vector3_size, vector3_size, ambient.as_ptr() as *const c_void);
-            gl::BufferSubData(gl::UNIFORM_BUFFER, 16 + (lights_no - 1) * light_size + 2 * vector3_size, vector3_size, diffuse.as_ptr() as *const c_void);
-            gl::BufferSubData(gl::UNIFORM_BUFFER, 16 + (lights_no - 1) * light_size + 3 * vector3_size, vector3_size, specular.as_ptr() as *const c_void);
-            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
         }
+        let grid = LightGrid::build(&self.uploaded, view, projection, viewport[2] as u32, viewport[3] as u32);
+        self.tiles_buffer.upload(&grid.tiles);
+        self.indices_buffer.upload(&grid.indices);
+
+        self.lights_buffer.bind(LIGHTS_TEXTURE_UNIT);
+        self.tiles_buffer.bind(LIGHT_TILES_TEXTURE_UNIT);
+        self.indices_buffer.bind(LIGHT_INDICES_TEXTURE_UNIT);
+        shader.set_int("lights", LIGHTS_TEXTURE_UNIT as i32);
+        shader.set_int("lightTiles", LIGHT_TILES_TEXTURE_UNIT as i32);
+        shader.set_int("lightIndices", LIGHT_INDICES_TEXTURE_UNIT as i32);
+        shader.set_int("tileSize", TILE_SIZE as i32);
+        shader.set_int("tilesX", grid.tiles_x as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, vec3};
+
+    use crate::lights::{Attenuation, Light, LightKind, Slots};
+
+    fn light(x: f32) -> Light {
+        let kind = LightKind::Point { position: Point3::new(x, 0., 0.), attenuation: Attenuation::NONE };
+        Light { kind, ambient: vec3(0., 0., 0.), diffuse: vec3(1., 1., 1.), specular: vec3(0., 0., 0.), range: None }
+    }
+
+    #[test]
+    fn removed_light_is_gone() {
+        let mut slots = Slots::default();
+        let first = slots.add(light(1.));
+        let second = slots.add(light(2.));
+        assert!(slots.remove(first));
+        assert!(!slots.remove(first));
+        assert!(slots.get(first).is_none());
+        assert!(slots.get_mut(first).is_none());
+        assert_eq!(slots.get(second).unwrap().light.position(), Some(Point3::new(2., 0., 0.)));
+    }
+
+    #[test]
+    fn reused_slot_does_not_answer_old_handle() {
+        let mut slots = Slots::default();
+        let old = slots.add(light(1.));
+        slots.remove(old);
+        let new = slots.add(light(2.));
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert!(slots.get(old).is_none());
+        assert!(!slots.remove(old));
+        assert_eq!(slots.get(new).unwrap().light.position(), Some(Point3::new(2., 0., 0.)));
+        assert_eq!(slots.slots.len(), 1);
     }
 }
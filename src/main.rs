@@ -1,80 +1,302 @@
 extern crate gl;
 extern crate glfw;
 
-use std::f32::consts::{FRAC_PI_8, PI};
+use std::{env, fs, process};
+use std::f32::consts::FRAC_PI_8;
+use std::path::Path;
 use std::sync::mpsc::Receiver;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use benchmark::BenchmarkReport;
+use camera_path::{CameraPath, DEFAULT_CAMERA_PATH, PathPlayer};
+use clock::Clock;
 use fps_calculator::FpsCalculator;
+use hud::{Hud, HudStats};
 use observer::RenderLoopObserver;
+use offscreen::Framebuffer;
+use options::Options;
+use recording::{Recorder, RecordingSettings};
+use xmas_tree::description::SceneDescription;
+use xmas_tree::editor::Editor;
 use xmas_tree::scene::Scene;
 
-use self::glfw::{Action, Context, Glfw, Key, MouseButtonLeft, Window, WindowEvent};
+use cgmath::{Deg, vec3};
 
+use self::glfw::{Action, Context, Glfw, Key, Modifiers, MouseButtonLeft, MouseButtonMiddle, MouseButtonRight, Window, WindowEvent};
+
+mod benchmark;
 mod camera;
+mod camera_path;
+mod capture;
+mod clock;
+mod collision;
 mod coords;
 mod model;
+mod font;
 mod fps_calculator;
+mod hud;
+mod light_grid;
 mod lights;
 mod material;
 mod observer;
+mod offscreen;
+mod options;
+mod overlay;
+mod picking;
+mod profiler;
+mod recording;
 mod shader;
+mod shadow;
+mod texture_buffer;
+mod wind;
 mod xmas_tree;
 
 // settings
 const SCR_WIDTH: u32 = 1920;
 const SCR_HEIGHT: u32 = 1080;
+/// Simulation time step used in headless mode when no fixed timestep was requested
+const HEADLESS_TIMESTEP: f32 = 1. / 60.;
+/// Change of the field of view per key press, in degrees
+const FOV_CHANGE: f32 = 5.;
+/// Where F4 saves the profiler summary
+const PROFILE_PATH: &str = "profile.csv";
+/// Clip planes move by that factor per key press
+const CLIP_PLANE_CHANGE: f32 = 1.25;
+/// Snow seed of benchmark runs when neither the options nor the scene set one, so they are comparable
+const BENCHMARK_SEED: u64 = 2020;
+/// Wind turns by that angle per key press, in degrees
+const WIND_TURN: f32 = 15.;
+/// Change of the wind strength per key press
+const WIND_STRENGTH_CHANGE: f32 = 0.25;
+/// Number of snowflakes changes by that factor per key press
+const SNOWFALL_CHANGE: f32 = 1.5;
+/// Scene lights turn around the tree by that angle per key press, in degrees
+const LIGHT_TURN: f32 = 15.;
 
 struct Main {
     last_cursor_x: f64,
     last_cursor_y: f64,
+    clock: Clock,
+    editor: Editor,
+    player: PathPlayer,
+    camera_path: String,
+    recording: RecordingSettings,
+    recorder: Option<Recorder>,
+    poster_scale: u32,
+    poster_supersampling: u32,
+    hud: Hud,
 }
 
 fn main() {
+    let options = Options::parse(env::args()).unwrap_or_else(|error| {
+        eprintln!("{}\n{}", error, options::USAGE);
+        process::exit(1);
+    });
+
     // glfw: initialize and configure
     // ------------------------------
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
-    let (mut window, events) = setup_window(&mut glfw);
-
-    // gl: load all OpenGL function pointers
-    gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
+    let (mut window, events) = setup_window(&mut glfw, &options);
+    setup_gl(&mut window);
 
-    unsafe {
-        gl::Enable(gl::MULTISAMPLE);
-        gl::Enable(gl::DEPTH_TEST);
-        gl::Enable(gl::CULL_FACE);
-        // gl::CullFace(gl::BACK);
-        // gl::FrontFace(gl::CCW);
+    let description = SceneDescription::load(&options.scene).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    let path = options.camera_path.as_ref().map(|path| CameraPath::load(path).unwrap_or_else(|error| {
+        eprintln!("Failed to load camera path: {}", error);
+        process::exit(1);
+    }));
+    let mut player = PathPlayer::new(path.unwrap_or_default());
+    player.play();
+    let seed = options.seed.or(description.seed)
+        .unwrap_or_else(|| if options.benchmark { BENCHMARK_SEED } else { rand::random() });
+    println!("Seed: {}", seed);
+    let mut scene = Scene::setup(&window, &description, seed);
+    if options.headless {
+        render_headless(&mut scene, &mut player, &options);
+        return;
+    }
+    if options.benchmark {
+        run_benchmark(&mut glfw, &mut window, &mut scene, &options);
+        return;
     }
 
-    let mut scene = Scene::setup(&window);
     let mut fps_calculator = FpsCalculator::new();
-    let mut main = Main { last_cursor_x: -1., last_cursor_y: -1. };
+    let fixed_timestep = options.fixed_timestep.map(|steps_per_second| 1. / steps_per_second as f32);
+    let mut main = Main { last_cursor_x: -1., last_cursor_y: -1., clock: Clock::new(fixed_timestep), editor: Editor::new(&options.layout),
+        player, camera_path: options.camera_path.clone().unwrap_or_else(|| DEFAULT_CAMERA_PATH.to_owned()),
+        recording: RecordingSettings {
+            output: options.record_output.clone(),
+            width: options.record_width,
+            height: options.record_height,
+            fps: options.record_fps,
+            duration: options.record_duration,
+        },
+        recorder: None,
+        poster_scale: options.poster_scale,
+        poster_supersampling: options.poster_supersampling,
+        hud: Hud::new() };
 
     // render loop
     while !window.should_close() {
         process_events(&mut main, &mut window, &events, &mut scene);
-        scene.next_frame();
+        // while recording every frame advances the simulation by the same step, no matter how long it takes
+        let steps = main.clock.tick();
+        let camera_dt = match &main.recorder {
+            Some(recorder) => {
+                if let Some(dt) = main.clock.recording_step(recorder.dt()) {
+                    scene.next_frame(dt);
+                }
+                recorder.dt()
+            }
+            None => {
+                for dt in steps {
+                    scene.next_frame(dt);
+                }
+                main.clock.frame_time()
+            }
+        };
+        scene.camera.update(camera_dt);
+        if main.player.is_playing() {
+            scene.camera.follow(main.player.advance(camera_dt));
+        }
         scene.draw();
+        let (width, height) = window.get_framebuffer_size();
+        let stats = HudStats {
+            fps: fps_calculator.fps(),
+            frame_times: fps_calculator.frame_times(),
+            snowflakes: scene.snowflakes(),
+            draw_calls: scene.draw_calls(),
+            camera: scene.camera.eye(),
+        };
+        main.hud.draw(&stats, width as u32, height as u32);
+        scene.profiler.end_frame();
+        // a paused recording waits, so that it doesn't get the same frame many times
+        if !main.clock.paused {
+            record_frame(&mut main, &mut scene, &window);
+        }
         window.swap_buffers();
         glfw.poll_events();
         fps_calculator.tick();
     }
 }
 
-fn setup_window(glfw: &mut Glfw) -> (Window, Receiver<(f64, WindowEvent)>) {
+/// Renders requested number of frames into an offscreen framebuffer and writes each of them as a PNG file.
+/// The window stays hidden, so it works also on machines without GPU, e.g. with Mesa's llvmpipe under Xvfb.
+/// The camera follows the camera path if one was given.
+fn render_headless(scene: &mut Scene, player: &mut PathPlayer, options: &Options) {
+    fs::create_dir_all(&options.output).unwrap_or_else(|_| panic!("Failed to create directory {}", options.output));
+    let dt = options.fixed_timestep.map_or(HEADLESS_TIMESTEP, |steps_per_second| 1. / steps_per_second as f32);
+    let framebuffer = Framebuffer::new(options.width, options.height, 4);
+    framebuffer.bind();
+    for frame in 0..options.frames {
+        scene.next_frame(dt);
+        if player.is_playing() {
+            scene.camera.follow(player.advance(dt));
+        }
+        scene.draw();
+        let path = format!("{}/frame_{:05}.png", options.output, frame);
+        capture::save_png(&path, framebuffer.width, framebuffer.height, &framebuffer.read_pixels())
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", path, e));
+    }
+    framebuffer.unbind();
+}
+
+fn run_benchmark(glfw: &mut Glfw, window: &mut Window, scene: &mut Scene, options: &Options) {
+    let frame_times = benchmark::run(glfw, window, scene, options.benchmark_frames, options.benchmark_warmup);
+    let (width, height) = window.get_framebuffer_size();
+    let report = BenchmarkReport::new(&options.scene, width as u32, height as u32, &frame_times);
+    println!("{}", report.summary());
+    if let Some(path) = &options.benchmark_report {
+        match report.save(path) {
+            Ok(()) => println!("Saved benchmark report to {}", path),
+            Err(error) => eprintln!("Failed to save benchmark report: {}", error),
+        }
+    }
+    if let Some(path) = &options.benchmark_baseline {
+        match BenchmarkReport::load(path) {
+            Ok(baseline) => println!("{}", report.compare(&baseline)),
+            Err(error) => eprintln!("Failed to load benchmark baseline: {}", error),
+        }
+    }
+}
+
+fn record_frame(main: &mut Main, scene: &mut Scene, window: &Window) {
+    let recorder = match &mut main.recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if let Err(error) = recorder.record(scene, window) {
+        eprintln!("Recording failed: {}", error);
+        main.recorder = None;
+    } else if recorder.is_finished() {
+        stop_recording(main);
+    }
+}
+
+/// Renders the current frame and saves it in the working directory, named after the millisecond it was taken
+fn save_capture(scene: &mut Scene, window: &Window, name: &str, scale: u32, supersampling: u32) {
+    let (width, height, pixels) = capture::render_poster(scene, window, scale, supersampling);
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    let path = capture::capture_path(name, timestamp, |p| Path::new(p).exists());
+    match capture::save_png(&path, width, height, &pixels) {
+        Ok(()) => println!("Saved {}x{} {} to {}", width, height, name, path),
+        Err(error) => eprintln!("Failed to save {}: {}", path, error),
+    }
+}
+
+fn stop_recording(main: &mut Main) {
+    if let Some(recorder) = main.recorder.take() {
+        println!("Recorded {} frames to {}", recorder.recorded_frames(), recorder.path());
+    }
+}
+
+fn setup_window(glfw: &mut Glfw, options: &Options) -> (Window, Receiver<(f64, WindowEvent)>) {
     glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
     glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
     glfw.window_hint(glfw::WindowHint::Samples(Some(4)));
+    glfw.window_hint(glfw::WindowHint::Visible(!options.headless));
 
-    let (mut window, events) = glfw.create_window(SCR_WIDTH, SCR_HEIGHT, "Rusted Christmas tree", glfw::WindowMode::Windowed)
+    let (mut window, events) = glfw.create_window(options.width, options.height, "Rusted Christmas tree", glfw::WindowMode::Windowed)
         .expect("Failed to create GLFW window");
     window.make_current();
     window.set_key_polling(true);
     window.set_framebuffer_size_polling(true);
     window.set_cursor_pos_polling(true);
+    window.set_mouse_button_polling(true);
+    window.set_scroll_polling(true);
     (window, events)
 }
 
+fn setup_gl(window: &mut Window) {
+    // gl: load all OpenGL function pointers
+    gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
+
+    unsafe {
+        gl::Enable(gl::MULTISAMPLE);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::CULL_FACE);
+        // gl::CullFace(gl::BACK);
+        // gl::FrontFace(gl::CCW);
+    }
+}
+
+fn turn_wind(scene: &mut Scene, angle: f32) {
+    scene.wind.turn(Deg(angle));
+    println!("Wind: {}", scene.wind.describe());
+}
+
+fn change_wind_strength(scene: &mut Scene, change: f32) {
+    scene.wind.change_strength(change);
+    println!("Wind: {}", scene.wind.describe());
+}
+
+fn change_snowfall(scene: &mut Scene, factor: f32) {
+    scene.change_snowfall(factor);
+    println!("Snowfall: {} snowflakes, about {:.0} landing per second", scene.snowflakes(), scene.snowfall_intensity());
+}
+
 fn process_events(main: &mut Main, window: &mut glfw::Window, events: &Receiver<(f64, glfw::WindowEvent)>, scene: &mut Scene) {
     let mut mouse_offset_x: f64 = 0.;
     let mut mouse_offset_y: f64 = 0.;
@@ -100,6 +322,128 @@ fn process_events(main: &mut Main, window: &mut glfw::Window, events: &Receiver<
             glfw::WindowEvent::Key(Key::Down, _, Action::Press, _) | glfw::WindowEvent::Key(Key::Down, _, Action::Repeat, _) => {
                 scene.camera.rotate_vertically(angle_change);
             },
+            glfw::WindowEvent::Key(Key::P, _, Action::Press, _) => main.clock.toggle_pause(),
+            glfw::WindowEvent::Key(Key::H, _, Action::Press, _) => scene.toggle_shadows(),
+            glfw::WindowEvent::Key(Key::R, _, Action::Press, _) => scene.reset_snow_cover(),
+            glfw::WindowEvent::Key(Key::Equal, _, Action::Press, _) | glfw::WindowEvent::Key(Key::KpAdd, _, Action::Press, _) => {
+                main.clock.speed_up();
+            },
+            glfw::WindowEvent::Key(Key::Minus, _, Action::Press, _) | glfw::WindowEvent::Key(Key::KpSubtract, _, Action::Press, _) => {
+                main.clock.slow_down();
+            },
+            glfw::WindowEvent::Key(Key::Num0, _, Action::Press, _) | glfw::WindowEvent::Key(Key::Kp0, _, Action::Press, _) => {
+                main.clock.reset_time_scale();
+            },
+            glfw::WindowEvent::Key(Key::Kp4, _, Action::Press, _) => turn_wind(scene, -WIND_TURN),
+            glfw::WindowEvent::Key(Key::Kp6, _, Action::Press, _) => turn_wind(scene, WIND_TURN),
+            glfw::WindowEvent::Key(Key::Kp8, _, Action::Press, _) => change_wind_strength(scene, WIND_STRENGTH_CHANGE),
+            glfw::WindowEvent::Key(Key::Kp2, _, Action::Press, _) => change_wind_strength(scene, -WIND_STRENGTH_CHANGE),
+            glfw::WindowEvent::Key(Key::Kp7, _, Action::Press, _) => scene.turn_lights(Deg(-LIGHT_TURN)),
+            glfw::WindowEvent::Key(Key::Kp9, _, Action::Press, _) => scene.turn_lights(Deg(LIGHT_TURN)),
+            glfw::WindowEvent::Key(Key::N, _, Action::Press, _) => scene.toggle_lights(),
+            glfw::WindowEvent::Key(Key::F, _, Action::Press, _) => scene.toggle_headlamp(),
+            glfw::WindowEvent::Key(Key::PageUp, _, Action::Press, _) => change_snowfall(scene, SNOWFALL_CHANGE),
+            glfw::WindowEvent::Key(Key::PageDown, _, Action::Press, _) => change_snowfall(scene, 1. / SNOWFALL_CHANGE),
+            glfw::WindowEvent::Key(Key::C, _, Action::Press, _) => scene.camera.toggle_mode(),
+            glfw::WindowEvent::Key(Key::O, _, Action::Press, _) => scene.camera.toggle_orthographic(),
+            glfw::WindowEvent::Key(Key::Comma, _, Action::Press, _) | glfw::WindowEvent::Key(Key::Comma, _, Action::Repeat, _) => {
+                scene.camera.change_fov(Deg(-FOV_CHANGE));
+            },
+            glfw::WindowEvent::Key(Key::Period, _, Action::Press, _) | glfw::WindowEvent::Key(Key::Period, _, Action::Repeat, _) => {
+                scene.camera.change_fov(Deg(FOV_CHANGE));
+            },
+            glfw::WindowEvent::Key(Key::LeftBracket, _, Action::Press, modifiers) => {
+                if modifiers.contains(Modifiers::Shift) {
+                    scene.camera.scale_clip_planes(1. / CLIP_PLANE_CHANGE, 1.);
+                } else {
+                    scene.camera.scale_clip_planes(1., 1. / CLIP_PLANE_CHANGE);
+                }
+            },
+            glfw::WindowEvent::Key(Key::RightBracket, _, Action::Press, modifiers) => {
+                if modifiers.contains(Modifiers::Shift) {
+                    scene.camera.scale_clip_planes(CLIP_PLANE_CHANGE, 1.);
+                } else {
+                    scene.camera.scale_clip_planes(1., CLIP_PLANE_CHANGE);
+                }
+            },
+            glfw::WindowEvent::Scroll(_, y) => scene.camera.zoom(y as f32),
+            glfw::WindowEvent::Key(Key::E, _, Action::Press, _) => main.editor.toggle(),
+            glfw::WindowEvent::Key(Key::M, _, Action::Press, _) => main.editor.cycle_material(scene),
+            glfw::WindowEvent::Key(Key::Delete, _, Action::Press, _) => main.editor.remove(scene),
+            glfw::WindowEvent::Key(Key::F5, _, Action::Press, _) => {
+                if let Err(error) = main.editor.save(scene) {
+                    eprintln!("Failed to save layout: {}", error);
+                }
+            },
+            glfw::WindowEvent::Key(Key::F9, _, Action::Press, _) => {
+                if let Err(error) = main.editor.load(scene) {
+                    eprintln!("Failed to load layout: {}", error);
+                }
+            },
+            glfw::WindowEvent::Key(Key::K, _, Action::Press, _) => {
+                main.player.path.capture(&scene.camera.pose());
+                println!("Captured keyframe {}", main.player.path.keyframes().len());
+            },
+            glfw::WindowEvent::Key(Key::F6, _, Action::Press, _) => {
+                if main.player.is_playing() {
+                    main.player.stop();
+                    scene.camera.follow(None);
+                } else {
+                    main.player.play();
+                }
+            },
+            glfw::WindowEvent::Key(Key::F7, _, Action::Press, _) => {
+                match main.player.path.save(&main.camera_path) {
+                    Ok(()) => println!("Saved {} keyframes to {}", main.player.path.keyframes().len(), main.camera_path),
+                    Err(error) => eprintln!("Failed to save camera path: {}", error),
+                }
+            },
+            glfw::WindowEvent::Key(Key::F8, _, Action::Press, _) => {
+                match CameraPath::load(&main.camera_path) {
+                    Ok(path) => {
+                        println!("Loaded {} keyframes from {}", path.keyframes().len(), main.camera_path);
+                        main.player.stop();
+                        scene.camera.follow(None);
+                        main.player.path = path;
+                    }
+                    Err(error) => eprintln!("Failed to load camera path: {}", error),
+                }
+            },
+            glfw::WindowEvent::Key(Key::L, _, Action::Press, _) => main.player.toggle_looping(),
+            glfw::WindowEvent::Key(Key::F1, _, Action::Press, _) => main.hud.toggle(),
+            glfw::WindowEvent::Key(Key::F3, _, Action::Press, _) => scene.profiler.toggle(),
+            glfw::WindowEvent::Key(Key::F4, _, Action::Press, _) => {
+                match scene.profiler.write_csv(PROFILE_PATH) {
+                    Ok(()) => println!("Saved profile to {}", PROFILE_PATH),
+                    Err(error) => eprintln!("Failed to save profile: {}", error),
+                }
+            },
+            glfw::WindowEvent::Key(Key::F12, _, Action::Press, modifiers) => {
+                if modifiers.contains(Modifiers::Shift) {
+                    save_capture(scene, window, "poster", main.poster_scale, main.poster_supersampling);
+                } else {
+                    save_capture(scene, window, "screenshot", 1, 1);
+                }
+            },
+            glfw::WindowEvent::Key(Key::F10, _, Action::Press, _) => {
+                if main.recorder.is_some() {
+                    stop_recording(main);
+                } else {
+                    match Recorder::start(&main.recording) {
+                        Ok(recorder) => {
+                            println!("Recording {}x{} at {} fps to {}", main.recording.width, main.recording.height, main.recording.fps, main.recording.output);
+                            main.recorder = Some(recorder);
+                        }
+                        Err(error) => eprintln!("Failed to start recording: {}", error),
+                    }
+                }
+            },
+            glfw::WindowEvent::MouseButton(MouseButtonLeft, Action::Press, _) => {
+                let ray = scene.camera.ray(main.last_cursor_x, main.last_cursor_y);
+                scene.hover(&ray);
+                main.editor.press(scene, &ray);
+            },
+            glfw::WindowEvent::MouseButton(MouseButtonLeft, Action::Release, _) => main.editor.release(),
             glfw::WindowEvent::CursorPos(x, y) => {
                 mouse_offset_x = x - main.last_cursor_x;
                 mouse_offset_y = y - main.last_cursor_y;
@@ -109,10 +453,30 @@ fn process_events(main: &mut Main, window: &mut glfw::Window, events: &Receiver<
             _ => {}
         }
     }
-    if window.get_mouse_button(MouseButtonLeft) == Action::Press && (mouse_offset_x != 0. || mouse_offset_y != 0.) {
+    let cursor_moved = mouse_offset_x != 0. || mouse_offset_y != 0.;
+    if main.editor.is_dragging() {
+        if cursor_moved {
+            let ray = scene.camera.ray(main.last_cursor_x, main.last_cursor_y);
+            main.editor.drag(scene, &ray);
+        }
+    } else if cursor_moved {
         let (width, height) = window.get_size();
         let max = width.max(height) as f32;
-        scene.camera.rotate_horizontally(-4. * PI / max * mouse_offset_x as f32);
-        scene.camera.rotate_vertically(-4. * PI / max * mouse_offset_y as f32);
+        let (x, y) = (mouse_offset_x as f32 / max, mouse_offset_y as f32 / max);
+        if window.get_mouse_button(MouseButtonLeft) == Action::Press {
+            scene.camera.drag(x, y);
+        } else if window.get_mouse_button(MouseButtonRight) == Action::Press || window.get_mouse_button(MouseButtonMiddle) == Action::Press {
+            scene.camera.pan(x, y);
+        }
+    }
+    let pressed = |key: Key| if window.get_key(key) == Action::Press { 1. } else { 0. };
+    scene.camera.set_fly_input(vec3(
+        pressed(Key::D) - pressed(Key::A),
+        pressed(Key::Space) - pressed(Key::LeftShift),
+        pressed(Key::W) - pressed(Key::S),
+    ));
+    if main.last_cursor_x >= 0. {
+        let ray = scene.camera.ray(main.last_cursor_x, main.last_cursor_y);
+        scene.hover(&ray);
     }
 }
@@ -8,7 +8,7 @@ use cgmath::prelude::*;
 
 use crate::shader::MATERIALS_UBO_BINDING_POINT;
 
-const MAX_MATERIALS: isize = 100;
+pub const MAX_MATERIALS: usize = 100;
 
 pub type MaterialId = f32;
 
@@ -42,7 +42,7 @@ impl Materials {
             let mut materials_ubo = 0 as u32;
             gl::GenBuffers(1, &mut materials_ubo);
             gl::BindBuffer(gl::UNIFORM_BUFFER, materials_ubo);
-            gl::BufferData(gl::UNIFORM_BUFFER, MAX_MATERIALS * Material::size(), ptr::null(), gl::STATIC_DRAW);
+            gl::BufferData(gl::UNIFORM_BUFFER, MAX_MATERIALS as isize * Material::size(), ptr::null(), gl::STATIC_DRAW);
             gl::BindBufferBase(gl::UNIFORM_BUFFER, MATERIALS_UBO_BINDING_POINT, materials_ubo);
             gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
             materials_ubo
@@ -50,6 +50,7 @@ impl Materials {
     }
 
     pub fn add(&mut self, material: Material) -> MaterialId {
+        assert!(self.materials.len() < MAX_MATERIALS, "At most {} materials are supported", MAX_MATERIALS);
         self.materials.push(material);
         let material_id = self.materials.len() as usize - 1;
         unsafe {
@@ -1,27 +1,82 @@
 use core::mem;
+use std::any::Any;
 
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Point3};
 
+use crate::collision::Collider;
+use crate::lights::Light;
 use crate::material::MaterialId;
+use crate::picking::Ray;
 use crate::shader::Shader;
+use crate::wind::Wind;
+
+/// Emission added to the instance under the cursor
+pub const HIGHLIGHT_EMISSION: f32 = 0.35;
 
 #[derive(Debug)]
 #[repr(C)]  // to make sure memory representation is like in the code
 pub struct Instance {
     pub model: Matrix4<f32>,
     pub material_id: MaterialId,
+    /// how strongly the instance glows in its diffuse colour, 0 for everything that doesn't emit light
+    pub emission: f32,
 }
 
 impl Instance {
     pub fn size() -> usize {
-        mem::size_of::<Matrix4<f32>>() + mem::size_of::<u32>()
+        mem::size_of::<Matrix4<f32>>() + mem::size_of::<u32>() + mem::size_of::<f32>()
+    }
+}
+
+/// Allows getting the concrete model back from a `dyn Model`, e.g. to edit it
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 
-pub trait Model {
-    /// Do all necessary things to advance the model to the next frame
-    fn next_frame(&mut self);
+pub trait Model: AsAny {
+    /// Short name shown e.g. by the profiler
+    fn name(&self) -> &'static str;
+
+    /// Do all necessary things to advance the model by dt seconds of simulation time
+    fn next_frame(&mut self, dt: f32);
+
+    /// Lets the wind of the coming frame move the model, called right before next_frame
+    fn blow(&mut self, _wind: &Wind) {}
+
+    /// Draw the model using given shader, returns the number of draw calls made
+    fn draw(&mut self, shader: &Shader) -> usize;
+
+    /// Lights the model shines with in the current frame
+    fn emitted_lights(&self) -> Vec<Light> {
+        vec![]
+    }
+
+    /// Triangles in world space falling snow can land on
+    fn snow_surface(&self) -> Vec<[Point3<f32>; 3]> {
+        vec![]
+    }
+
+    /// Shapes falling snow bumps into in the current frame
+    fn colliders(&self) -> Vec<Collider> {
+        vec![]
+    }
+
+    /// Finds the instance hit by the ray, returns its index and distance along the ray
+    fn pick(&self, _ray: &Ray) -> Option<(usize, f32)> {
+        None
+    }
 
-    /// Draw the model using given shader
-    fn draw(&mut self, shader: &Shader);
+    /// Makes given instance stand out, None removes the highlight
+    fn highlight(&mut self, _instance: Option<usize>) {}
 }
@@ -0,0 +1,105 @@
+extern crate gl;
+
+use std::os::raw::c_void;
+
+/// Framebuffer object that is not attached to any window, everything drawn while it's bound ends up in its renderbuffers.
+/// Rendering happens into a multisampled framebuffer, which is resolved into a plain one before reading pixels back.
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    multisampled_fbo: u32,
+    resolved_fbo: u32,
+    renderbuffers: [u32; 3],
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, samples: u32) -> Self {
+        unsafe {
+            let mut renderbuffers = [0_u32; 3];
+            gl::GenRenderbuffers(3, renderbuffers.as_mut_ptr());
+            let [ms_color, ms_depth, resolved_color] = renderbuffers;
+
+            let multisampled_fbo = Self::create_fbo();
+            gl::BindRenderbuffer(gl::RENDERBUFFER, ms_color);
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, gl::RGBA8, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, ms_color);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, ms_depth);
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, gl::DEPTH24_STENCIL8, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, ms_depth);
+            Self::ensure_complete("multisampled");
+
+            let resolved_fbo = Self::create_fbo();
+            gl::BindRenderbuffer(gl::RENDERBUFFER, resolved_color);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, resolved_color);
+            Self::ensure_complete("resolved");
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            Framebuffer { width, height, multisampled_fbo, resolved_fbo, renderbuffers }
+        }
+    }
+
+    unsafe fn create_fbo() -> u32 {
+        let mut fbo = 0_u32;
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        fbo
+    }
+
+    unsafe fn ensure_complete(name: &str) {
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("ERROR::FRAMEBUFFER::{}::INCOMPLETE status: {:#x}", name, status);
+        }
+    }
+
+    /// Makes all subsequent drawing go into this framebuffer
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.multisampled_fbo);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+
+    /// Switches drawing back to the window
+    pub fn unbind(&self) {
+        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, 0) }
+    }
+
+    /// Returns RGBA pixels of the last drawn frame, rows ordered from top to bottom
+    pub fn read_pixels(&self) -> Vec<u8> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let mut pixels: Vec<u8> = vec![0; (4 * self.width * self.height) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.multisampled_fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.resolved_fbo);
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.resolved_fbo);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut c_void);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.multisampled_fbo);
+        }
+        flip_rows(&mut pixels, 4 * self.width as usize);
+        pixels
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.multisampled_fbo);
+            gl::DeleteFramebuffers(1, &self.resolved_fbo);
+            gl::DeleteRenderbuffers(3, self.renderbuffers.as_ptr());
+        }
+    }
+}
+
+/// OpenGL returns rows from bottom to top, images are stored from top to bottom
+fn flip_rows(pixels: &mut [u8], row_size: usize) {
+    let rows = pixels.len() / row_size;
+    for row in 0..rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((rows - 1 - row) * row_size);
+        top[row * row_size..(row + 1) * row_size].swap_with_slice(&mut bottom[..row_size]);
+    }
+}
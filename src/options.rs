@@ -0,0 +1,170 @@
+use std::str::FromStr;
+
+use crate::{SCR_HEIGHT, SCR_WIDTH};
+use crate::xmas_tree::description::DEFAULT_SCENE;
+
+pub const USAGE: &str = "Usage: rusted-christmas-tree [--headless] [--frames N] [--output DIR] [--width W] [--height H] [--scene FILE] [--seed N] [--fixed-timestep STEPS_PER_SECOND] [--layout FILE] [--camera-path FILE]\n    [--record-output FILE_OR_DIR] [--record-width W] [--record-height H] [--record-fps N] [--record-duration SECONDS]\n    [--poster-scale N] [--poster-supersampling N]\n    [--benchmark] [--benchmark-frames N] [--benchmark-warmup N] [--benchmark-report FILE] [--benchmark-baseline FILE]";
+
+/// Command line options.
+/// Without any options the scene is rendered in a visible window until it gets closed.
+/// With --headless the window is hidden and the given number of frames is rendered into an offscreen framebuffer
+/// and written as PNG files into the output directory.
+/// --scene points at the scene description file.
+/// --seed makes the animation reproducible, without it the seed from scene description or a random one is used.
+/// --fixed-timestep makes the simulation advance in steps of the same length instead of the time each frame took.
+/// --layout is the file bauble layout is saved to and loaded from in edit mode.
+/// --record-* options set up recordings started with F10: a file ending with .gif gets an animated GIF, anything else
+/// is a directory for numbered PNG files. Recordings have their own resolution and simulated framerate.
+/// --poster-* options set up posters taken with Shift+F12, they're the window size times the scale,
+/// with every pixel averaged from supersampling^2 rendered ones.
+/// --benchmark renders a fixed number of frames with vsync off while the camera orbits the scene and prints frame time statistics,
+/// they can be saved as a JSON report and compared with an earlier one. Warm-up frames rendered before are not measured.
+/// It needs a visible window, so it cannot be combined with --headless.
+/// --camera-path makes the camera follow keyframes from the file, it's also where captured keyframes are saved to.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub headless: bool,
+    pub frames: u32,
+    pub output: String,
+    pub width: u32,
+    pub height: u32,
+    pub scene: String,
+    pub seed: Option<u64>,
+    pub fixed_timestep: Option<u32>,
+    pub layout: String,
+    pub camera_path: Option<String>,
+    pub record_output: String,
+    pub record_width: u32,
+    pub record_height: u32,
+    pub record_fps: u32,
+    pub record_duration: f32,
+    pub poster_scale: u32,
+    pub poster_supersampling: u32,
+    pub benchmark: bool,
+    pub benchmark_frames: usize,
+    pub benchmark_warmup: usize,
+    pub benchmark_report: Option<String>,
+    pub benchmark_baseline: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { headless: false, frames: 1, output: String::from("frames"), width: SCR_WIDTH, height: SCR_HEIGHT, scene: String::from(DEFAULT_SCENE), seed: None, fixed_timestep: None, layout: String::from("layout.ron"), camera_path: None,
+            record_output: String::from("recording.gif"), record_width: 640, record_height: 360, record_fps: 25, record_duration: 5.,
+            poster_scale: 4, poster_supersampling: 2,
+            benchmark: false, benchmark_frames: 1000, benchmark_warmup: 60, benchmark_report: None, benchmark_baseline: None }
+    }
+}
+
+impl Options {
+    /// Parses options from given arguments, the first one (program name) is skipped
+    pub fn parse<I: IntoIterator<Item=String>>(args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = parse_value(&arg, args.next())?,
+                "--output" => options.output = parse_value(&arg, args.next())?,
+                "--width" => options.width = parse_value(&arg, args.next())?,
+                "--height" => options.height = parse_value(&arg, args.next())?,
+                "--scene" => options.scene = parse_value(&arg, args.next())?,
+                "--seed" => options.seed = Some(parse_value(&arg, args.next())?),
+                "--fixed-timestep" => options.fixed_timestep = Some(parse_value(&arg, args.next())?),
+                "--layout" => options.layout = parse_value(&arg, args.next())?,
+                "--camera-path" => options.camera_path = Some(parse_value(&arg, args.next())?),
+                "--record-output" => options.record_output = parse_value(&arg, args.next())?,
+                "--record-width" => options.record_width = parse_value(&arg, args.next())?,
+                "--record-height" => options.record_height = parse_value(&arg, args.next())?,
+                "--record-fps" => options.record_fps = parse_value(&arg, args.next())?,
+                "--record-duration" => options.record_duration = parse_value(&arg, args.next())?,
+                "--poster-scale" => options.poster_scale = parse_value(&arg, args.next())?,
+                "--poster-supersampling" => options.poster_supersampling = parse_value(&arg, args.next())?,
+                "--benchmark" => options.benchmark = true,
+                "--benchmark-frames" => options.benchmark_frames = parse_value(&arg, args.next())?,
+                "--benchmark-warmup" => options.benchmark_warmup = parse_value(&arg, args.next())?,
+                "--benchmark-report" => options.benchmark_report = Some(parse_value(&arg, args.next())?),
+                "--benchmark-baseline" => options.benchmark_baseline = Some(parse_value(&arg, args.next())?),
+                _ => return Err(format!("Unknown option '{}'", arg)),
+            }
+        }
+        if options.width == 0 || options.height == 0 {
+            return Err("Width and height have to be positive".to_owned());
+        }
+        if options.record_width == 0 || options.record_height == 0 {
+            return Err("Recording width and height have to be positive".to_owned());
+        }
+        if options.record_fps == 0 || options.record_duration <= 0. {
+            return Err("Recording framerate and duration have to be positive".to_owned());
+        }
+        if options.poster_scale == 0 || options.poster_supersampling == 0 {
+            return Err("Poster scale and supersampling have to be positive".to_owned());
+        }
+        if options.fixed_timestep == Some(0) {
+            return Err("Fixed timestep needs at least one step per second".to_owned());
+        }
+        if options.benchmark_frames == 0 {
+            return Err("Benchmark needs at least one frame".to_owned());
+        }
+        if options.benchmark && options.headless {
+            return Err("Benchmark runs in a window, it cannot be headless".to_owned());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_value<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for option '{}'", option))?;
+    value.parse().map_err(|_| format!("Invalid value '{}' for option '{}'", value, option))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use crate::options::Options;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[rstest(line, expected,
+    case("xmas", Options::default()),
+    case("xmas --headless", Options { headless: true, ..Options::default() }),
+    case("xmas --headless --frames 10 --output out", Options { headless: true, frames: 10, output: String::from("out"), ..Options::default() }),
+    case("xmas --width 320 --height 240", Options { width: 320, height: 240, ..Options::default() }),
+    case("xmas --scene scenes/other.ron", Options { scene: String::from("scenes/other.ron"), ..Options::default() }),
+    case("xmas --seed 2020", Options { seed: Some(2020), ..Options::default() }),
+    case("xmas --fixed-timestep 120", Options { fixed_timestep: Some(120), ..Options::default() }),
+    case("xmas --layout my_tree.ron", Options { layout: String::from("my_tree.ron"), ..Options::default() }),
+    case("xmas --camera-path flight.ron", Options { camera_path: Some(String::from("flight.ron")), ..Options::default() }),
+    case("xmas --benchmark --benchmark-frames 300 --benchmark-baseline before.json", Options { benchmark: true, benchmark_frames: 300, benchmark_baseline: Some(String::from("before.json")), ..Options::default() }),
+    case("xmas --benchmark --benchmark-warmup 0", Options { benchmark: true, benchmark_warmup: 0, ..Options::default() }),
+    case("xmas --poster-scale 2 --poster-supersampling 3", Options { poster_scale: 2, poster_supersampling: 3, ..Options::default() }),
+    case("xmas --record-output frames --record-fps 30 --record-duration 2.5", Options { record_output: String::from("frames"), record_fps: 30, record_duration: 2.5, ..Options::default() }),
+    )]
+    fn parses_valid_options(line: &str, expected: Options) {
+        assert_eq!(Options::parse(args(line)), Ok(expected));
+    }
+
+    #[rstest(line,
+    case("xmas --unknown"),
+    case("xmas --frames"),
+    case("xmas --frames ten"),
+    case("xmas --width -1"),
+    case("xmas --width 0"),
+    case("xmas --height 0"),
+    case("xmas --seed -5"),
+    case("xmas --fixed-timestep 0"),
+    case("xmas --poster-scale 0"),
+    case("xmas --record-width 0"),
+    case("xmas --record-height 0"),
+    case("xmas --record-fps 0"),
+    case("xmas --record-duration -1"),
+    case("xmas --benchmark-frames 0"),
+    case("xmas --headless --benchmark"),
+    )]
+    fn rejects_invalid_options(line: &str) {
+        assert!(Options::parse(args(line)).is_err());
+    }
+}
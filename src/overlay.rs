@@ -0,0 +1,144 @@
+extern crate gl;
+
+use std::{mem, ptr};
+use std::os::raw::c_void;
+
+use crate::font::{Atlas, GLYPH_HEIGHT, GLYPH_WIDTH, SOLID};
+use crate::shader::Shader;
+
+/// Units 0-3 are taken by the shadow map and the light buffers
+const ATLAS_TEXTURE_UNIT: u32 = 4;
+/// Position in pixels, texture coordinates and colour
+const VERTEX_FLOATS: usize = 8;
+const INITIAL_CAPACITY: usize = 4096;
+
+pub type Colour = [f32; 4];
+
+/// Text and rectangles drawn over the scene in window pixels, with (0, 0) in the top left corner.
+/// Everything added during a frame is drawn in a single draw call by draw().
+pub struct Overlay {
+    shader: Shader,
+    atlas: Atlas,
+    texture: u32,
+    vao: u32,
+    vbo: u32,
+    /// in vertices
+    capacity: usize,
+    vertices: Vec<f32>,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        let shader = Shader::new("src/xmas_tree/shaders/overlay.vert", "src/xmas_tree/shaders/overlay.frag");
+        let atlas = Atlas::new();
+        unsafe {
+            let mut texture = 0_u32;
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R8 as i32, atlas.width as i32, atlas.height as i32, 0, gl::RED, gl::UNSIGNED_BYTE,
+                           atlas.pixels.as_ptr() as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            let (mut vao, mut vbo) = (0_u32, 0_u32);
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (INITIAL_CAPACITY * VERTEX_FLOATS * mem::size_of::<f32>()) as isize, ptr::null(), gl::DYNAMIC_DRAW);
+            let stride = (VERTEX_FLOATS * mem::size_of::<f32>()) as i32;
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<f32>()) as *const c_void);
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (4 * mem::size_of::<f32>()) as *const c_void);
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            Overlay { shader, atlas, texture, vao, vbo, capacity: INITIAL_CAPACITY, vertices: vec![] }
+        }
+    }
+
+    /// Each pixel of the font takes scale x scale pixels of the window, lines are separated with '\n'
+    pub fn text(&mut self, text: &str, x: f32, y: f32, scale: f32, colour: Colour) {
+        for (line_no, line) in text.lines().enumerate() {
+            let top = y + line_no as f32 * line_height(scale);
+            for (i, c) in line.chars().enumerate() {
+                let left = x + i as f32 * char_width(scale);
+                let uv = self.atlas.glyph(c);
+                self.quad([left, top, left + GLYPH_WIDTH as f32 * scale, top + GLYPH_HEIGHT as f32 * scale], uv, colour);
+            }
+        }
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, colour: Colour) {
+        let [left, top, right, bottom] = self.atlas.glyph(SOLID);
+        // sampling only the middle of the glyph keeps the edges of the neighbouring ones out
+        let uv = [(left + right) / 2., (top + bottom) / 2., (left + right) / 2., (top + bottom) / 2.];
+        self.quad([x, y, x + width, y + height], uv, colour);
+    }
+
+    fn quad(&mut self, [left, top, right, bottom]: [f32; 4], [u0, v0, u1, v1]: [f32; 4], colour: Colour) {
+        let [r, g, b, a] = colour;
+        for (x, y, u, v) in &[(left, top, u0, v0), (left, bottom, u0, v1), (right, bottom, u1, v1),
+            (left, top, u0, v0), (right, bottom, u1, v1), (right, top, u1, v0)] {
+            self.vertices.extend_from_slice(&[*x, *y, *u, *v, r, g, b, a]);
+        }
+    }
+
+    /// Draws everything added since the previous call over whatever is in the framebuffer of given size
+    pub fn draw(&mut self, width: u32, height: u32) {
+        let count = self.vertices.len() / VERTEX_FLOATS;
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            if count > self.capacity {
+                while self.capacity < count {
+                    self.capacity *= 2;
+                }
+                gl::BufferData(gl::ARRAY_BUFFER, (self.capacity * VERTEX_FLOATS * mem::size_of::<f32>()) as isize, ptr::null(), gl::DYNAMIC_DRAW);
+            }
+            gl::BufferSubData(gl::ARRAY_BUFFER, 0, mem::size_of_val(self.vertices.as_slice()) as isize, self.vertices.as_ptr() as *const c_void);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            gl::ActiveTexture(gl::TEXTURE0 + ATLAS_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            self.shader.set_int("atlas", ATLAS_TEXTURE_UNIT as i32);
+            self.shader.set_vec2("screenSize", width as f32, height as f32);
+
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::UseProgram(self.shader.id);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, count as i32);
+            gl::BindVertexArray(0);
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        self.vertices.clear();
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/// Horizontal distance between neighbouring characters
+pub fn char_width(scale: f32) -> f32 {
+    (GLYPH_WIDTH + 1) as f32 * scale
+}
+
+pub fn line_height(scale: f32) -> f32 {
+    (GLYPH_HEIGHT + 2) as f32 * scale
+}
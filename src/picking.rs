@@ -0,0 +1,187 @@
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+use cgmath::prelude::*;
+
+/// Half-line starting at origin, points on it are origin + t * direction for t >= 0
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+/// Model and its instance hit by a ray
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pick {
+    pub model: usize,
+    pub instance: usize,
+    pub distance: f32,
+}
+
+impl Ray {
+    /// Ray going from the camera through a point given in normalized device coordinates, i.e. -1 to 1 on both axes
+    pub fn from_ndc(x: f32, y: f32, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Self {
+        let inverse = (projection * view).invert().expect("View and projection have to be invertible");
+        let unproject = |z: f32| {
+            let p = inverse * Vector4::new(x, y, z, 1.);
+            Point3::new(p.x / p.w, p.y / p.w, p.z / p.w)
+        };
+        let near = unproject(-1.);
+        let far = unproject(1.);
+        Ray { origin: near, direction: (far - near).normalize() }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + t * self.direction
+    }
+
+    /// Same ray expressed in the local space of a model with given transformation.
+    /// Direction isn't normalized, so distances along both rays stay the same.
+    pub fn in_local_space(&self, transform: &Matrix4<f32>) -> Option<Ray> {
+        let inverse = transform.invert()?;
+        Some(Ray { origin: inverse.transform_point(self.origin), direction: inverse.transform_vector(self.direction) })
+    }
+
+    /// Distance to the closest intersection with the sphere
+    pub fn intersect_sphere(&self, center: Point3<f32>, radius: f32) -> Option<f32> {
+        let to_origin = self.origin - center;
+        let a = self.direction.magnitude2();
+        let b = 2. * self.direction.dot(to_origin);
+        let c = to_origin.magnitude2() - radius * radius;
+        let discriminant = b * b - 4. * a * c;
+        if discriminant < 0. {
+            return None;
+        }
+        let sqrt = discriminant.sqrt();
+        let near = (-b - sqrt) / (2. * a);
+        let far = (-b + sqrt) / (2. * a);
+        if near >= 0. {
+            Some(near)
+        } else if far >= 0. {
+            Some(far)   // starts inside the sphere
+        } else {
+            None
+        }
+    }
+
+    /// Distance to the intersection with the triangle, both sides of it are hit
+    pub fn intersect_triangle(&self, triangle: &[Point3<f32>; 3]) -> Option<f32> {
+        // Möller–Trumbore algorithm
+        let edge1 = triangle[1] - triangle[0];
+        let edge2 = triangle[2] - triangle[0];
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-7 {
+            return None;    // parallel to the triangle
+        }
+        let to_origin = self.origin - triangle[0];
+        let u = to_origin.dot(p) / determinant;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(edge1);
+        let v = self.direction.dot(q) / determinant;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = edge2.dot(q) / determinant;
+        if t >= 0. { Some(t) } else { None }
+    }
+
+    /// Distance to the intersection with the plane y = height, in the rectangle with given half-sizes around the Y axis
+    pub fn intersect_horizontal_rect(&self, height: f32, half_x: f32, half_z: f32) -> Option<f32> {
+        if self.direction.y == 0. {
+            return None;
+        }
+        let t = (height - self.origin.y) / self.direction.y;
+        let hit = self.at(t);
+        if t >= 0. && hit.x.abs() <= half_x && hit.z.abs() <= half_z { Some(t) } else { None }
+    }
+}
+
+/// Closest of the hits together with its index
+pub fn closest<I: IntoIterator<Item=Option<f32>>>(hits: I) -> Option<(usize, f32)> {
+    hits.into_iter()
+        .enumerate()
+        .filter_map(|(i, hit)| hit.map(|t| (i, t)))
+        .fold(None, |closest, (i, t)| match closest {
+            Some((_, closest_t)) if closest_t <= t => closest,
+            _ => Some((i, t)),
+        })
+}
+
+impl Pick {
+    /// Nearest instance of all models, given the hit of every model in order.
+    /// Any model hit closer hides the ones behind it, e.g. the tree hides baubles on its far side.
+    pub fn nearest<I: IntoIterator<Item=Option<(usize, f32)>>>(model_hits: I) -> Option<Pick> {
+        let hits: Vec<Option<(usize, f32)>> = model_hits.into_iter().collect();
+        closest(hits.iter().map(|hit| hit.map(|(_, distance)| distance)))
+            .map(|(model, distance)| Pick { model, instance: hits[model].unwrap().0, distance })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, perspective, Point3, vec3};
+    use cgmath::prelude::*;
+    use rstest::*;
+
+    use crate::picking::{closest, Pick, Ray};
+
+    fn ray(origin: Point3<f32>, direction: [f32; 3]) -> Ray {
+        Ray { origin, direction: direction.into() }
+    }
+
+    #[rstest(r, expected,
+    case(ray(Point3::new(0., 0., 5.), [0., 0., -1.]), Some(4.)),
+    case(ray(Point3::new(0., 0., 0.), [0., 0., -1.]), Some(1.)),
+    case(ray(Point3::new(0., 0., 5.), [0., 0., 1.]), None),
+    case(ray(Point3::new(0., 2., 5.), [0., 0., -1.]), None),
+    )]
+    fn intersects_sphere(r: Ray, expected: Option<f32>) {
+        assert_eq!(r.intersect_sphere(Point3::new(0., 0., 0.), 1.), expected);
+    }
+
+    #[rstest(r, expected,
+    case(ray(Point3::new(0., 5., 0.), [0., -1., 0.]), Some(5.)),
+    case(ray(Point3::new(3., 5., 0.), [0., -1., 0.]), None),
+    case(ray(Point3::new(0., 5., 0.), [1., 0., 0.]), None),
+    case(ray(Point3::new(0., -5., 0.), [0., -1., 0.]), None),
+    )]
+    fn intersects_horizontal_rect(r: Ray, expected: Option<f32>) {
+        assert_eq!(r.intersect_horizontal_rect(0., 1., 1.), expected);
+    }
+
+    #[rstest(r, expected,
+    case(ray(Point3::new(0.2, 0.2, 5.), [0., 0., -1.]), Some(5.)),
+    case(ray(Point3::new(0.2, 0.2, -5.), [0., 0., 1.]), Some(5.)),
+    case(ray(Point3::new(0.8, 0.8, 5.), [0., 0., -1.]), None),
+    case(ray(Point3::new(0.2, 0.2, 5.), [1., 0., 0.]), None),
+    )]
+    fn intersects_triangle(r: Ray, expected: Option<f32>) {
+        let triangle = [Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 1., 0.)];
+        assert_eq!(r.intersect_triangle(&triangle), expected);
+    }
+
+    #[test]
+    fn ray_from_screen_center_goes_along_view_direction() {
+        let view = Matrix4::look_at(Point3::new(0., 0., 10.), Point3::new(0., 0., 0.), vec3(0., 1., 0.));
+        let projection = perspective(Deg(45.), 1.5, 0.1, 100.);
+        let ray = Ray::from_ndc(0., 0., &view, &projection);
+        assert!((ray.direction - vec3(0., 0., -1.)).magnitude2() < 1e-6, "{:?}", ray.direction);
+        assert!((ray.origin.z - 9.9).abs() < 1e-3, "{:?}", ray.origin);
+    }
+
+    #[test]
+    fn finds_closest_hit() {
+        assert_eq!(closest(vec![None, Some(3.), Some(1.), Some(2.)]), Some((2, 1.)));
+        assert_eq!(closest(vec![None, None]), None);
+    }
+
+    #[test]
+    fn closer_model_hides_instances_behind_it() {
+        // the ground far away, the tree in front of a bauble
+        let hits = vec![Some((0, 20.)), Some((0, 3.)), Some((7, 5.))];
+        assert_eq!(Pick::nearest(hits), Some(Pick { model: 1, instance: 0, distance: 3. }));
+        assert_eq!(Pick::nearest(vec![None, None, Some((7, 5.))]), Some(Pick { model: 2, instance: 7, distance: 5. }));
+        assert_eq!(Pick::nearest(vec![None, None]), None);
+    }
+}
@@ -0,0 +1,229 @@
+extern crate gl;
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::time::Instant;
+
+/// Number of last measurements of every section the statistics are computed from, a summary is printed that often
+pub const PROFILER_WINDOW: usize = 100;
+/// GPU results are read that many measurements later, so waiting for them doesn't stall the pipeline
+const QUERY_LATENCY: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    Update,
+    Shadow,
+    Draw,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Update => "update",
+            Stage::Shadow => "shadow",
+            Stage::Draw => "draw",
+        }
+    }
+}
+
+/// Last measurements of a single section, in milliseconds
+#[derive(Debug, Default)]
+struct Samples {
+    values: VecDeque<f32>,
+}
+
+impl Samples {
+    fn push(&mut self, value: f32) {
+        if self.values.len() == PROFILER_WINDOW {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    fn average(&self) -> f32 {
+        if self.values.is_empty() { 0. } else { self.values.iter().sum::<f32>() / self.values.len() as f32 }
+    }
+
+    fn max(&self) -> f32 {
+        self.values.iter().copied().fold(0., f32::max)
+    }
+}
+
+/// Ring of GL_TIME_ELAPSED queries, results of earlier ones are collected before they're reused
+struct GpuTimer {
+    queries: [u32; QUERY_LATENCY],
+    pending: [bool; QUERY_LATENCY],
+    next: usize,
+}
+
+impl GpuTimer {
+    fn new() -> Self {
+        let mut queries = [0; QUERY_LATENCY];
+        unsafe { gl::GenQueries(QUERY_LATENCY as i32, queries.as_mut_ptr()) }
+        GpuTimer { queries, pending: [false; QUERY_LATENCY], next: 0 }
+    }
+
+    /// Starts the next query, returns the result of the one it replaces in milliseconds
+    fn begin(&mut self) -> Option<f32> {
+        let query = self.queries[self.next];
+        let result = if self.pending[self.next] {
+            let mut nanoseconds = 0_u64;
+            unsafe { gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanoseconds) }
+            Some(nanoseconds as f32 / 1e6)
+        } else {
+            None
+        };
+        unsafe { gl::BeginQuery(gl::TIME_ELAPSED, query) }
+        self.pending[self.next] = true;
+        self.next = (self.next + 1) % QUERY_LATENCY;
+        result
+    }
+
+    fn end(&self) {
+        unsafe { gl::EndQuery(gl::TIME_ELAPSED) }
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteQueries(QUERY_LATENCY as i32, self.queries.as_ptr()) }
+    }
+}
+
+struct Section {
+    model: usize,
+    name: &'static str,
+    stage: Stage,
+    cpu: Samples,
+    gpu: Samples,
+    timer: GpuTimer,
+}
+
+/// Statistics of a section over the last PROFILER_WINDOW measurements, times in milliseconds
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub model: usize,
+    pub name: &'static str,
+    pub stage: Stage,
+    pub samples: usize,
+    pub cpu_average: f32,
+    pub cpu_max: f32,
+    pub gpu_average: f32,
+    pub gpu_max: f32,
+}
+
+/// Measures how long every stage of every model takes, both on the CPU and on the GPU.
+/// Does nothing until enabled, GPU times come from timer queries and lag a few measurements behind.
+pub struct Profiler {
+    enabled: bool,
+    sections: Vec<Section>,
+    frames: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler { enabled: false, sections: vec![], frames: 0 }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.sections.clear();
+        self.frames = 0;
+        println!("Profiler {}", if self.enabled { "on" } else { "off" });
+    }
+
+    /// Runs f, measuring it as given stage of the model
+    pub fn measure<T, F: FnOnce() -> T>(&mut self, model: usize, name: &'static str, stage: Stage, f: F) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let index = match self.sections.iter().position(|s| s.model == model && s.stage == stage) {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section { model, name, stage, cpu: Samples::default(), gpu: Samples::default(), timer: GpuTimer::new() });
+                self.sections.len() - 1
+            }
+        };
+        let section = &mut self.sections[index];
+        if let Some(gpu) = section.timer.begin() {
+            section.gpu.push(gpu);
+        }
+        let start = Instant::now();
+        let result = f();
+        section.cpu.push(start.elapsed().as_secs_f32() * 1000.);
+        section.timer.end();
+        result
+    }
+
+    /// Prints the summary every PROFILER_WINDOW frames
+    pub fn end_frame(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.frames += 1;
+        if self.frames.is_multiple_of(PROFILER_WINDOW) {
+            print!("{}", format_table(&self.summary()));
+        }
+    }
+
+    pub fn summary(&self) -> Vec<Summary> {
+        self.sections.iter()
+            .map(|s| Summary {
+                model: s.model,
+                name: s.name,
+                stage: s.stage,
+                samples: s.cpu.values.len(),
+                cpu_average: s.cpu.average(),
+                cpu_max: s.cpu.max(),
+                gpu_average: s.gpu.average(),
+                gpu_max: s.gpu.max(),
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, path: &str) -> Result<(), String> {
+        fs::write(path, format_csv(&self.summary())).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+fn format_table(summary: &[Summary]) -> String {
+    let mut table = format!("{:<20} {:<7} {:>9} {:>9} {:>9} {:>9}\n", "model", "stage", "cpu avg", "cpu max", "gpu avg", "gpu max");
+    for s in summary {
+        writeln!(table, "{:<20} {:<7} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+                 format!("{} {}", s.model, s.name), s.stage.name(), s.cpu_average, s.cpu_max, s.gpu_average, s.gpu_max).unwrap();
+    }
+    table
+}
+
+fn format_csv(summary: &[Summary]) -> String {
+    let mut csv = String::from("model,name,stage,samples,cpu_avg_ms,cpu_max_ms,gpu_avg_ms,gpu_max_ms\n");
+    for s in summary {
+        writeln!(csv, "{},{},{},{},{:.4},{:.4},{:.4},{:.4}",
+                 s.model, s.name, s.stage.name(), s.samples, s.cpu_average, s.cpu_max, s.gpu_average, s.gpu_max).unwrap();
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profiler::{format_csv, PROFILER_WINDOW, Samples, Stage, Summary};
+
+    #[test]
+    fn keeps_only_last_samples() {
+        let mut samples = Samples::default();
+        for i in 0..PROFILER_WINDOW + 10 {
+            samples.push(i as f32);
+        }
+        assert_eq!(samples.values.len(), PROFILER_WINDOW);
+        assert_eq!(samples.max(), (PROFILER_WINDOW + 9) as f32);
+        assert_eq!(samples.average(), 10. + (PROFILER_WINDOW - 1) as f32 / 2.);
+    }
+
+    #[test]
+    fn writes_summary_as_csv() {
+        let summary = [Summary { model: 2, name: "snow", stage: Stage::Draw, samples: 100, cpu_average: 0.25, cpu_max: 1., gpu_average: 0.5, gpu_max: 0.75 }];
+        assert_eq!(format_csv(&summary), "model,name,stage,samples,cpu_avg_ms,cpu_max_ms,gpu_avg_ms,gpu_max_ms\n\
+                                          2,snow,draw,100,0.2500,1.0000,0.5000,0.7500\n");
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use glfw::Window;
+
+use crate::capture::save_png;
+use crate::offscreen::Framebuffer;
+use crate::xmas_tree::scene::Scene;
+
+/// Colour quantization speed, from 1 (best quality) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+const SAMPLES: u32 = 4;
+
+pub struct RecordingSettings {
+    /// a file ending with .gif or a directory for numbered PNG files
+    pub output: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /// in seconds of simulated time
+    pub duration: f32,
+}
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Png(String),
+}
+
+/// Records frames of the scene at its own resolution, independent of the window.
+/// Each recorded frame advances the simulation by the same step, no matter how long rendering takes.
+pub struct Recorder {
+    framebuffer: Framebuffer,
+    output: Output,
+    path: String,
+    fps: u32,
+    frame: u32,
+    frames: u32,
+}
+
+impl Recorder {
+    pub fn start(settings: &RecordingSettings) -> Result<Self, String> {
+        if settings.fps == 0 || settings.duration <= 0. {
+            return Err("framerate and duration have to be positive".to_owned());
+        }
+        let output = if settings.output.ends_with(".gif") {
+            if settings.width > u16::MAX as u32 || settings.height > u16::MAX as u32 {
+                return Err(format!("GIF cannot be larger than {0}x{0}", u16::MAX));
+            }
+            let file = File::create(&settings.output).map_err(|e| format!("{}: {}", settings.output, e))?;
+            let mut encoder = gif::Encoder::new(BufWriter::new(file), settings.width as u16, settings.height as u16, &[])
+                .map_err(|e| format!("{}: {}", settings.output, e))?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("{}: {}", settings.output, e))?;
+            Output::Gif(encoder)
+        } else {
+            fs::create_dir_all(&settings.output).map_err(|e| format!("{}: {}", settings.output, e))?;
+            Output::Png(settings.output.clone())
+        };
+        Ok(Recorder {
+            framebuffer: Framebuffer::new(settings.width, settings.height, SAMPLES),
+            output,
+            path: settings.output.clone(),
+            fps: settings.fps,
+            frame: 0,
+            frames: (settings.duration * settings.fps as f32).round().max(1.) as u32,
+        })
+    }
+
+    /// Simulated time between recorded frames
+    pub fn dt(&self) -> f32 {
+        1. / self.fps as f32
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn recorded_frames(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.frames
+    }
+
+    /// Draws the scene into the recording framebuffer and stores it as the next frame, then switches drawing back to the window
+    pub fn record(&mut self, scene: &mut Scene, window: &Window) -> Result<(), String> {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        self.framebuffer.bind();
+        scene.camera.set_framebuffer_size(width, height);
+        scene.draw();
+        let mut pixels = self.framebuffer.read_pixels();
+        self.framebuffer.unbind();
+        let (window_width, window_height) = window.get_framebuffer_size();
+        unsafe { gl::Viewport(0, 0, window_width, window_height) }
+        scene.camera.on_window_resize(window);
+
+        match &mut self.output {
+            Output::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, GIF_SPEED);
+                frame.delay = gif_delay(self.frame, self.fps);
+                encoder.write_frame(&frame).map_err(|e| format!("{}: {}", self.path, e))?;
+            }
+            Output::Png(dir) => {
+                let path = format!("{}/frame_{:05}.png", dir, self.frame);
+                save_png(&path, width, height, &pixels).map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+/// GIF delays are whole hundredths of a second, rounding errors are carried over to next frames,
+/// so e.g. 30 fps alternates between 3 and 4 and the animation doesn't drift
+fn gif_delay(frame: u32, fps: u32) -> u16 {
+    let end = |frame: u32| (frame as f64 * 100. / fps as f64).round() as u32;
+    (end(frame + 1) - end(frame)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use crate::recording::gif_delay;
+
+    #[rstest(fps, expected,
+    case(25, &[4, 4, 4]),
+    case(30, &[3, 4, 3]),
+    case(50, &[2, 2, 2]),
+    )]
+    fn gif_delays_keep_framerate(fps: u32, expected: &[u16]) {
+        let delays: Vec<u16> = (0..3).map(|frame| gif_delay(frame, fps)).collect();
+        assert_eq!(delays, expected);
+    }
+}
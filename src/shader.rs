@@ -6,10 +6,12 @@ use std::io::Read;
 use std::ptr;
 use std::str;
 
+use cgmath::Matrix4;
+use cgmath::prelude::*;
+
 use self::gl::types::*;
 
 pub const CAMERA_UBO_BINDING_POINT: u32 = 0;
-pub const LIGHTS_UBO_BINDING_POINT: u32 = 1;
 pub const MATERIALS_UBO_BINDING_POINT: u32 = 2;
 
 pub struct Shader {
@@ -19,6 +21,7 @@ pub struct Shader {
 #[derive(Debug)]
 enum ShaderType {
     VertexShader,
+    GeometryShader,
     FragmentShader,
     Program,
 }
@@ -37,28 +40,106 @@ impl Shader {
             gl::DeleteShader(fragment_shader);
 
             shader.bind_camera_ubo();
-            shader.bind_lights_ubo();
             shader.bind_materials_ubo();
             shader
         }
     }
 
-    unsafe fn bind_camera_ubo(&self) {
-        let c_name = CString::new("Camera").unwrap();
-        let uniform_block_index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
-        gl::UniformBlockBinding(self.id, uniform_block_index, CAMERA_UBO_BINDING_POINT);
+    /// Program without a fragment shader, whose outputs named by varyings are captured with transform feedback,
+    /// interleaved in one buffer in the given order
+    pub fn with_feedback(vertex_path: &str, varyings: &[&str]) -> Shader {
+        unsafe {
+            let shader_program = gl::CreateProgram();
+            let shader = Shader { id: shader_program };
+            let vertex_shader = shader.add_vertex_shader(vertex_path);
+            let names: Vec<CString> = varyings.iter().map(|v| CString::new(*v).unwrap()).collect();
+            let pointers: Vec<*const GLchar> = names.iter().map(|n| n.as_ptr()).collect();
+            gl::TransformFeedbackVaryings(shader_program, pointers.len() as i32, pointers.as_ptr(), gl::INTERLEAVED_ATTRIBS);
+            gl::LinkProgram(shader_program);
+            ensure_compilation_success(ShaderType::Program, shader_program);
+
+            gl::DeleteShader(vertex_shader);
+            shader
+        }
     }
 
-    unsafe fn bind_lights_ubo(&self) {
-        let c_name = CString::new("Lights").unwrap();
+    /// Program without a fragment shader for passes with rasterization discarded, e.g. counting primitives emitted by the geometry shader
+    pub fn with_geometry(vertex_path: &str, geometry_path: &str) -> Shader {
+        unsafe {
+            let shader_program = gl::CreateProgram();
+            let shader = Shader { id: shader_program };
+            let vertex_shader = shader.add_vertex_shader(vertex_path);
+            let geometry_shader = shader.add_geometry_shader(geometry_path);
+            gl::LinkProgram(shader_program);
+            ensure_compilation_success(ShaderType::Program, shader_program);
+
+            gl::DeleteShader(vertex_shader);
+            gl::DeleteShader(geometry_shader);
+            shader
+        }
+    }
+
+    unsafe fn bind_camera_ubo(&self) {
+        let c_name = CString::new("Camera").unwrap();
         let uniform_block_index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
-        gl::UniformBlockBinding(self.id, uniform_block_index, LIGHTS_UBO_BINDING_POINT);
+        if uniform_block_index != gl::INVALID_INDEX {   // not every shader uses all blocks
+            gl::UniformBlockBinding(self.id, uniform_block_index, CAMERA_UBO_BINDING_POINT);
+        }
     }
 
     unsafe fn bind_materials_ubo(&self) {
         let c_name = CString::new("Materials").unwrap();
         let uniform_block_index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
-        gl::UniformBlockBinding(self.id, uniform_block_index, MATERIALS_UBO_BINDING_POINT);
+        if uniform_block_index != gl::INVALID_INDEX {   // not every shader uses all blocks
+            gl::UniformBlockBinding(self.id, uniform_block_index, MATERIALS_UBO_BINDING_POINT);
+        }
+    }
+
+    pub fn set_int(&self, name: &str, value: i32) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::Uniform1i(self.uniform_location(name), value);
+        }
+    }
+
+    pub fn set_float(&self, name: &str, value: f32) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::Uniform1f(self.uniform_location(name), value);
+        }
+    }
+
+    pub fn set_vec2(&self, name: &str, x: f32, y: f32) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::Uniform2f(self.uniform_location(name), x, y);
+        }
+    }
+
+    pub fn set_vec3(&self, name: &str, value: &[f32; 3]) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::Uniform3fv(self.uniform_location(name), 1, value.as_ptr());
+        }
+    }
+
+    pub fn set_vec4(&self, name: &str, value: &[f32; 4]) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::Uniform4fv(self.uniform_location(name), 1, value.as_ptr());
+        }
+    }
+
+    pub fn set_mat4(&self, name: &str, value: &Matrix4<f32>) {
+        unsafe {
+            gl::UseProgram(self.id);
+            gl::UniformMatrix4fv(self.uniform_location(name), 1, gl::FALSE, value.as_ptr());
+        }
+    }
+
+    fn uniform_location(&self, name: &str) -> GLint {
+        let c_name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.id, c_name.as_ptr()) }
     }
 
     fn add_vertex_shader(&self, path: &str) -> u32 {
@@ -73,6 +154,18 @@ impl Shader {
         }
     }
 
+    fn add_geometry_shader(&self, path: &str) -> u32 {
+        let shader_source = load_from_file(path);
+        unsafe {
+            let geometry_shader = gl::CreateShader(gl::GEOMETRY_SHADER);
+            gl::ShaderSource(geometry_shader, 1, &shader_source.as_ptr(), ptr::null());
+            gl::CompileShader(geometry_shader);
+            ensure_compilation_success(ShaderType::GeometryShader, geometry_shader);
+            gl::AttachShader(self.id, geometry_shader);
+            geometry_shader
+        }
+    }
+
     fn add_fragment_shader(&self, path: &str) -> u32 {
         let shader_source = load_from_file(path);
         unsafe {
@@ -0,0 +1,151 @@
+extern crate gl;
+
+use std::ptr;
+
+use cgmath::{Deg, Matrix4, ortho, perspective, Point3, vec3, Vector3};
+use cgmath::prelude::*;
+
+use crate::lights::{Light, LightKind};
+use crate::shader::Shader;
+
+const SHADOW_MAP_SIZE: i32 = 2048;
+const SHADOW_FOV: Deg<f32> = Deg(120.);
+const SHADOW_NEAR: f32 = 0.5;
+const SHADOW_FAR: f32 = 50.;
+/// Directional lights are placed that far from the origin, they see a box of that half-width
+const DIRECTIONAL_DISTANCE: f32 = 25.;
+const DIRECTIONAL_EXTENT: f32 = 15.;
+/// Texture unit the shadow map is bound to while drawing the scene
+pub const SHADOW_MAP_TEXTURE_UNIT: u32 = 0;
+
+/// Depth of the scene as seen from a single light, drawing it first allows the lighting shader to tell whether
+/// a fragment is lit by that light or is in a shadow.
+/// Point lights are treated like a spotlight looking at the origin with a wide field of view,
+/// spot lights see their own cone and directional lights see a box around the origin.
+pub struct ShadowMap {
+    fbo: u32,
+    depth_texture: u32,
+    pub shader: Shader,
+    light_space: Matrix4<f32>,
+}
+
+impl ShadowMap {
+    pub fn new(light: &Light) -> Self {
+        let depth_texture = Self::create_depth_texture();
+        let fbo = Self::create_fbo(depth_texture);
+        let shader = Shader::new("src/xmas_tree/shaders/shadow.vert", "src/xmas_tree/shaders/shadow.frag");
+        let light_space = Self::light_space(light);
+        shader.set_mat4("lightSpace", &light_space);
+        ShadowMap { fbo, depth_texture, shader, light_space }
+    }
+
+    /// Makes the shadows fall from the light's new place
+    pub fn set_light(&mut self, light: &Light) {
+        self.light_space = Self::light_space(light);
+        self.shader.set_mat4("lightSpace", &self.light_space);
+    }
+
+    fn create_depth_texture() -> u32 {
+        unsafe {
+            let mut depth_texture = 0_u32;
+            gl::GenTextures(1, &mut depth_texture);
+            gl::BindTexture(gl::TEXTURE_2D, depth_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as i32, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, 0,
+                           gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            // everything outside of the shadow map is lit
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+            let border = [1.0_f32; 4];
+            gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            depth_texture
+        }
+    }
+
+    fn create_fbo(depth_texture: u32) -> u32 {
+        unsafe {
+            let mut fbo = 0_u32;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth_texture, 0);
+            // depth only, no colour at all
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            fbo
+        }
+    }
+
+    fn light_space(light: &Light) -> Matrix4<f32> {
+        match light.kind {
+            LightKind::Directional { direction } => {
+                let direction = direction.normalize();
+                let position = Point3::origin() - direction * DIRECTIONAL_DISTANCE;
+                let e = DIRECTIONAL_EXTENT;
+                ortho(-e, e, -e, e, SHADOW_NEAR, 2. * DIRECTIONAL_DISTANCE) * Self::view(position, direction)
+            }
+            LightKind::Point { position, .. } => {
+                let direction = (Point3::origin() - position).normalize();
+                perspective(SHADOW_FOV, 1., SHADOW_NEAR, SHADOW_FAR) * Self::view(position, direction)
+            }
+            LightKind::Spot { position, direction, outer_angle, .. } => {
+                let fov = Deg((2. * outer_angle.0).min(SHADOW_FOV.0));
+                perspective(fov, 1., SHADOW_NEAR, SHADOW_FAR) * Self::view(position, direction.normalize())
+            }
+        }
+    }
+
+    fn view(position: Point3<f32>, direction: Vector3<f32>) -> Matrix4<f32> {
+        // looking straight down or up, Y axis cannot be used as "up"
+        let up = if direction.y.abs() > 0.99 { vec3(0., 0., 1.) } else { vec3(0., 1., 0.) };
+        Matrix4::look_at(position, position + direction, up)
+    }
+
+    /// Prepares drawing depth of the scene, everything has to be drawn using self.shader until end() is called
+    pub fn begin(&self) -> ShadowPass {
+        let mut previous_fbo = 0;
+        let mut previous_viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_fbo);
+            gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            // pushing depth a bit away from the light removes most of the shadow acne
+            gl::Enable(gl::POLYGON_OFFSET_FILL);
+            gl::PolygonOffset(2., 4.);
+        }
+        ShadowPass { previous_fbo: previous_fbo as u32, previous_viewport }
+    }
+
+    /// Restores drawing into the framebuffer used before begin() and makes the shadow map available to given shader
+    pub fn end(&self, pass: ShadowPass, shader: &Shader) {
+        let viewport = pass.previous_viewport;
+        unsafe {
+            gl::Disable(gl::POLYGON_OFFSET_FILL);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, pass.previous_fbo);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            gl::ActiveTexture(gl::TEXTURE0 + SHADOW_MAP_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, self.depth_texture);
+        }
+        shader.set_int("shadowMap", SHADOW_MAP_TEXTURE_UNIT as i32);
+        shader.set_mat4("lightSpace", &self.light_space);
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.depth_texture);
+        }
+    }
+}
+
+/// State to be restored after drawing the shadow map
+pub struct ShadowPass {
+    previous_fbo: u32,
+    previous_viewport: [i32; 4],
+}
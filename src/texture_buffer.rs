@@ -0,0 +1,64 @@
+extern crate gl;
+
+use std::{mem, ptr};
+use std::os::raw::c_void;
+
+/// Buffer exposed to shaders as a buffer texture (samplerBuffer), unlike uniform blocks it can hold any number of elements.
+/// Storage is reallocated with twice the size whenever the data doesn't fit anymore.
+pub struct TextureBuffer {
+    buffer: u32,
+    texture: u32,
+    capacity: isize,
+}
+
+impl TextureBuffer {
+    /// Format is the internal format of a single texel, e.g. gl::RGBA32F, capacity is in bytes and has to be positive
+    pub fn new(format: u32, initial_capacity: isize) -> Self {
+        assert!(initial_capacity > 0, "Texture buffer capacity has to be positive");
+        unsafe {
+            let mut buffer = 0_u32;
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER, initial_capacity, ptr::null(), gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+
+            let mut texture = 0_u32;
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_BUFFER, texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, format, buffer);
+            gl::BindTexture(gl::TEXTURE_BUFFER, 0);
+            TextureBuffer { buffer, texture, capacity: initial_capacity }
+        }
+    }
+
+    pub fn upload<T>(&mut self, data: &[T]) {
+        let size = mem::size_of_val(data) as isize;
+        unsafe {
+            gl::BindBuffer(gl::TEXTURE_BUFFER, self.buffer);
+            if size > self.capacity {
+                while self.capacity < size {
+                    self.capacity *= 2;
+                }
+                gl::BufferData(gl::TEXTURE_BUFFER, self.capacity, ptr::null(), gl::DYNAMIC_DRAW);
+            }
+            gl::BufferSubData(gl::TEXTURE_BUFFER, 0, size, data.as_ptr() as *const c_void);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        }
+    }
+
+    pub fn bind(&self, texture_unit: u32) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.texture);
+        }
+    }
+}
+
+impl Drop for TextureBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use cgmath::{Deg, Point3, Rad, vec3, Vector3};
+use cgmath::prelude::*;
+use serde::Deserialize;
+
+use crate::shader::Shader;
+
+/// Turbulence is the curl of a vector potential, each of its components is a sum of these waves:
+/// wave vector, angular frequency and phase
+const WAVES: [[([f32; 3], f32, f32); 3]; 3] = [
+    [([0.31, 0.52, -0.17], 0.41, 0.0), ([-0.63, 0.12, 0.44], 0.73, 1.7), ([0.22, -0.35, 0.71], 1.13, 4.1)],
+    [([0.47, -0.21, 0.36], 0.53, 2.3), ([0.18, 0.66, -0.29], 0.91, 5.2), ([-0.41, 0.27, -0.58], 1.37, 0.9)],
+    [([-0.26, 0.43, 0.55], 0.47, 3.6), ([0.59, -0.38, -0.12], 0.83, 1.1), ([0.14, 0.29, -0.68], 1.21, 2.8)],
+];
+/// Brings the typical speed of the turbulence close to 1 before it's scaled by its strength
+const TURBULENCE_NORMALIZATION: f32 = 0.6;
+/// Gusts are a sum of sine waves with these angular frequencies and phases
+const GUSTS: [(f32, f32); 3] = [(0.37, 0.), (0.89, 1.3), (2.13, 4.2)];
+
+/// Wind settings, speeds are in world units per second.
+/// Direction is in degrees around the Y axis, 0 blows towards +X and 90 towards +Z. Calm air when not given.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindParameters {
+    pub direction: f32,
+    pub strength: f32,
+    /// gusts make the wind that much stronger or weaker at most, as a fraction of its strength
+    pub gustiness: f32,
+    /// typical speed of the swirls added everywhere
+    pub turbulence: f32,
+}
+
+impl WindParameters {
+    /// Returns name of the invalid field together with the reason
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if self.strength < 0. {
+            return Err(("strength", "cannot be negative".to_owned()));
+        }
+        if self.turbulence < 0. {
+            return Err(("turbulence", "cannot be negative".to_owned()));
+        }
+        if !(0. ..=1.).contains(&self.gustiness) {
+            return Err(("gustiness", "has to be between 0 and 1".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Air movement shared by all models: a global wind blowing in gusts plus divergence-free turbulence varying in space and time
+pub struct Wind {
+    direction: Rad<f32>,
+    strength: f32,
+    gustiness: f32,
+    turbulence: f32,
+    time: f32,
+}
+
+impl Wind {
+    pub fn new(parameters: &WindParameters) -> Self {
+        Wind {
+            direction: Deg(parameters.direction).into(),
+            strength: parameters.strength,
+            gustiness: parameters.gustiness,
+            turbulence: parameters.turbulence,
+            time: 0.,
+        }
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn turn(&mut self, angle: Deg<f32>) {
+        self.direction = (self.direction + Rad::from(angle)).normalize();
+    }
+
+    pub fn change_strength(&mut self, change: f32) {
+        self.strength = (self.strength + change).max(0.);
+    }
+
+    /// Strength and direction in degrees, as given in the parameters
+    pub fn describe(&self) -> String {
+        format!("{:.2} towards {:.0}°", self.strength, Deg::from(self.direction).0)
+    }
+
+    /// Global wind including gusts, the same everywhere
+    pub fn velocity(&self) -> Vector3<f32> {
+        let gust = GUSTS.iter().map(|(omega, phase)| (omega * self.time + phase).sin()).sum::<f32>() / GUSTS.len() as f32;
+        let strength = self.strength * (1. + self.gustiness * gust);
+        vec3(self.direction.cos(), 0., self.direction.sin()) * strength
+    }
+
+    /// Swirling part of the wind at the point
+    pub fn turbulence_at(&self, point: Point3<f32>) -> Vector3<f32> {
+        let p = point.to_vec();
+        // gradients of the potential's components
+        let gradients: Vec<Vector3<f32>> = WAVES.iter()
+            .map(|waves| waves.iter()
+                .map(|(k, omega, phase)| Vector3::from(*k) * (Vector3::from(*k).dot(p) + omega * self.time + phase).cos())
+                .sum())
+            .collect();
+        let (x, y, z) = (gradients[0], gradients[1], gradients[2]);
+        let curl = vec3(z.y - y.z, x.z - z.x, y.x - x.y);
+        curl * self.turbulence * TURBULENCE_NORMALIZATION
+    }
+
+    /// Everything the air does at the point
+    pub fn velocity_at(&self, point: Point3<f32>) -> Vector3<f32> {
+        self.velocity() + self.turbulence_at(point)
+    }
+
+    /// Gives a shader the turbulence waves, they never change so it's enough to do it once, see windVelocity() in snow_update.vert
+    pub fn set_wave_uniforms(shader: &Shader) {
+        for (component, waves) in WAVES.iter().enumerate() {
+            for (i, (k, omega, phase)) in waves.iter().enumerate() {
+                let index = component * waves.len() + i;
+                shader.set_vec4(&format!("windWaves[{}]", index), &[k[0], k[1], k[2], *omega]);
+                shader.set_float(&format!("windPhases[{}]", index), *phase);
+            }
+        }
+    }
+
+    /// Makes the current wind available to a shader which got the waves from set_wave_uniforms()
+    pub fn set_uniforms(&self, shader: &Shader) {
+        let velocity = self.velocity();
+        shader.set_vec3("wind", &[velocity.x, velocity.y, velocity.z]);
+        shader.set_float("windTime", self.time);
+        shader.set_float("turbulence", self.turbulence * TURBULENCE_NORMALIZATION);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Point3, vec3, Vector3};
+    use cgmath::prelude::*;
+
+    use crate::wind::{Wind, WindParameters};
+
+    #[test]
+    fn blows_in_given_direction() {
+        let wind = Wind::new(&WindParameters { direction: 90., strength: 2., ..WindParameters::default() });
+        assert!((wind.velocity() - vec3(0., 0., 2.)).magnitude() < 1e-5);
+        assert_eq!(wind.turbulence_at(Point3::new(1., 2., 3.)), vec3(0., 0., 0.));
+    }
+
+    #[test]
+    fn gusts_stay_within_gustiness() {
+        let mut wind = Wind::new(&WindParameters { strength: 2., gustiness: 0.5, ..WindParameters::default() });
+        wind.turn(Deg(30.));
+        for _ in 0..1000 {
+            wind.advance(0.1);
+            let speed = wind.velocity().magnitude();
+            assert!((1. ..=3.).contains(&speed), "{}", speed);
+        }
+    }
+
+    #[test]
+    fn turbulence_has_no_divergence() {
+        let mut wind = Wind::new(&WindParameters { turbulence: 1., ..WindParameters::default() });
+        wind.advance(3.7);
+        let h = 1e-2;
+        let point = Point3::new(1.3, -2.1, 0.7);
+        let derivative = |axis: usize| {
+            let mut offset = Vector3::zero();
+            offset[axis] = h;
+            (wind.turbulence_at(point + offset)[axis] - wind.turbulence_at(point - offset)[axis]) / (2. * h)
+        };
+        let divergence = derivative(0) + derivative(1) + derivative(2);
+        assert!(divergence.abs() < 1e-2, "{}", divergence);
+        assert!(wind.turbulence_at(point).magnitude() > 0.1);
+    }
+}
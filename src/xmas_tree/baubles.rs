@@ -1,118 +1,115 @@
 use core::f32::consts::PI;
-use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_6, FRAC_PI_8};
 use std::iter::FromIterator;
 
 use cgmath::{Matrix4, Point3, vec3, Vector3};
+use cgmath::prelude::*;
 
+use crate::collision::Collider;
 use crate::coords::CylindricalPoint3;
-use crate::material::{Material, MaterialId, Materials};
-use crate::model::{Instance, Model};
+use crate::material::MaterialId;
+use crate::model::{HIGHLIGHT_EMISSION, Instance, Model};
+use crate::picking::{closest, Ray};
 use crate::shader::Shader;
+use crate::wind::Wind;
 use crate::xmas_tree::mesh::{Mesh, Vertex};
 
-struct Bauble {
-    center: CylindricalPoint3<f32>,
-    material_id: MaterialId,
+/// How far a bauble swings per unit of wind speed
+const SWING_PER_WIND: f32 = 0.04;
+/// Baubles hang on short strings, they can't swing further than that
+const MAX_SWING: f32 = 0.12;
+/// How quickly a bauble follows the wind, per second
+const SWING_RESPONSE: f32 = 3.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bauble {
+    pub center: CylindricalPoint3<f32>,
+    pub material_id: MaterialId,
 }
 
 pub struct Baubles {
     mesh: Mesh,
+    radius: f32,
     baubles: Vec<Bauble>,
+    /// how far the wind moved each bauble from its center, missing ones don't move
+    swings: Vec<Vector3<f32>>,
+    /// where the current wind pushes each bauble to
+    swing_targets: Vec<Vector3<f32>>,
+    highlighted: Option<usize>,
 }
 
 impl Baubles {
-    pub fn new(materials: &mut Materials) -> Self {
-        let precision = 8 as u32;
-        let radius = 0.2 as f32;
-
-        let ambient: Vector3<f32> = vec3(0.1745, 0.01175, 0.01175);
-        let diffuse: Vector3<f32> = vec3(0.61424, 0.04136, 0.04136);
-        let specular: Vector3<f32> = vec3(0.727811, 0.626959, 0.626959);
-        let shininess: f32 = 76.8;
-        let red = Material { ambient, diffuse, specular, shininess };
-        let red_id = materials.add(red);
-
-        let ambient: Vector3<f32> = vec3(0.01175, 0.01175, 0.1745);
-        let diffuse: Vector3<f32> = vec3(0.04136, 0.04136, 0.61424);
-        let specular: Vector3<f32> = vec3(0.626959, 0.626959, 0.61424);
-        let shininess: f32 = 76.8;
-        let blue = Material { ambient, diffuse, specular, shininess };
-        let blue_id = materials.add(blue);
-
-        let ambient: Vector3<f32> = vec3(0.1745, 0.1745, 0.01175);
-        let diffuse: Vector3<f32> = vec3(0.61424, 0.61424, 0.04136);
-        let specular: Vector3<f32> = vec3(0.727811, 0.727811, 0.626959);
-        let shininess: f32 = 76.8;
-        let yellow = Material { ambient, diffuse, specular, shininess };
-        let yellow_id = materials.add(yellow);
-
-        let ambient: Vector3<f32> = vec3(0.01175, 0.1745, 0.1745);
-        let diffuse: Vector3<f32> = vec3(0.04136, 0.61424, 0.61424);
-        let specular: Vector3<f32> = vec3(0.626959, 0.727811, 0.727811);
-        let shininess: f32 = 76.8;
-        let light_blue = Material { ambient, diffuse, specular, shininess };
-        let light_blue_id = materials.add(light_blue);
-
-        let ambient: Vector3<f32> = vec3(0.1745, 0.01175, 0.1745);
-        let diffuse: Vector3<f32> = vec3(0.61424, 0.04136, 0.61424);
-        let specular: Vector3<f32> = vec3(0.727811, 0.626959, 0.727811);
-        let shininess: f32 = 76.8;
-        let violet = Material { ambient, diffuse, specular, shininess };
-        let violet_id = materials.add(violet);
-
-        let baubles: Vec<Bauble> = vec![
-            Bauble { center: CylindricalPoint3::new(0., 0., 2.7), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(1.1, -0.5, 1.3), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(1.1, 1.7, 1.3), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(1.5, 1.2, 0.25), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(1.5, -1.7, 0.25), material_id: light_blue_id },
-            Bauble { center: CylindricalPoint3::new(2.2, 1.0, -0.85), material_id: light_blue_id },
-            Bauble { center: CylindricalPoint3::new(2.2, 3. * FRAC_PI_4, -0.85), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(2.2, -0.2, -0.85), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(3., FRAC_PI_2, -1.8), material_id: violet_id },
-            Bauble { center: CylindricalPoint3::new(3., -FRAC_PI_2, -1.8), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(3., -FRAC_PI_4 - 3., -1.8), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(3., 3.6, -1.8), material_id: violet_id },
-            Bauble { center: CylindricalPoint3::new(3., 0.2, -1.8), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 1. * FRAC_PI_6, -3.), material_id: light_blue_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 2. * FRAC_PI_6, -3.), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 4. * FRAC_PI_6, -3.), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 5. * FRAC_PI_6, -3.), material_id: violet_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 6. * FRAC_PI_6, -3.), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 8. * FRAC_PI_6, -3.), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 9. * FRAC_PI_6, -3.), material_id: light_blue_id },
-            Bauble { center: CylindricalPoint3::new(3.6, 11. * FRAC_PI_6, -3.), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(4., 3. * FRAC_PI_8, -4.1), material_id: light_blue_id },
-            Bauble { center: CylindricalPoint3::new(4., 4. * FRAC_PI_8, -4.1), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(4., 5. * FRAC_PI_8, -4.1), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(4., 7. * FRAC_PI_8, -4.1), material_id: violet_id },
-            Bauble { center: CylindricalPoint3::new(4., 11. * FRAC_PI_8, -4.1), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(4., 12. * FRAC_PI_8, -4.1), material_id: blue_id },
-            Bauble { center: CylindricalPoint3::new(4., 13. * FRAC_PI_8, -4.1), material_id: yellow_id },
-            Bauble { center: CylindricalPoint3::new(4., 17. * FRAC_PI_8, -4.1), material_id: red_id },
-            Bauble { center: CylindricalPoint3::new(4., 21. * FRAC_PI_8, -4.1), material_id: blue_id },
-        ];
-
+    pub fn new(radius: f32, precision: u32, baubles: Vec<Bauble>) -> Self {
         let mut vertices: Vec<Vertex> = Vec::with_capacity(2 * precision.pow(2) as usize);
         let mut indices: Vec<u32> = Vec::with_capacity(3 * 4 * precision.pow(2) as usize);
 
         Self::gen_sphere(&mut vertices, &mut indices, Point3::new(0., 0., 0.), radius, precision);
 
         let mesh = Mesh::new(vertices, indices, baubles.len());
+        let baubles = Self { mesh, radius, baubles, swings: vec![], swing_targets: vec![], highlighted: None };
+        baubles.fill_instances();
+        baubles
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn baubles(&self) -> &[Bauble] {
+        &self.baubles
+    }
+
+    /// Adds a bauble and returns its index
+    pub fn add(&mut self, bauble: Bauble) -> usize {
+        self.baubles.push(bauble);
+        self.fill_instances();
+        self.baubles.len() - 1
+    }
 
+    pub fn move_to(&mut self, index: usize, center: CylindricalPoint3<f32>) {
+        self.baubles[index].center = center;
+        self.fill_instances();
+    }
+
+    pub fn set_material(&mut self, index: usize, material_id: MaterialId) {
+        self.baubles[index].material_id = material_id;
+        self.fill_instances();
+    }
+
+    /// Indices of the baubles after the removed one decrease by one
+    pub fn remove(&mut self, index: usize) {
+        self.baubles.remove(index);
+        if index < self.swings.len() {
+            self.swings.remove(index);
+        }
+        self.highlighted = None;
+        self.fill_instances();
+    }
+
+    pub fn replace_all(&mut self, baubles: Vec<Bauble>) {
+        self.baubles = baubles;
+        self.swings.clear();
+        self.highlighted = None;
+        self.fill_instances();
+    }
+
+    /// Center of the bauble moved by the wind
+    fn position(&self, index: usize) -> Point3<f32> {
+        let center: Point3<f32> = self.baubles[index].center.into();
+        center + self.swings.get(index).copied().unwrap_or_else(Vector3::zero)
+    }
+
+    fn fill_instances(&self) {
         let instances = Vec::from_iter(
-            baubles.iter()
-                .map(|b| {
-                    let center_cartesian: Point3<f32> = b.center.into();
-                    let center_arr: [f32; 3] = center_cartesian.into();
-                    Instance { model: Matrix4::from_translation(Vector3::from(center_arr)), material_id : b.material_id }
+            self.baubles.iter().enumerate()
+                .map(|(i, b)| {
+                    let emission = if self.highlighted == Some(i) { HIGHLIGHT_EMISSION } else { 0. };
+                    Instance { model: Matrix4::from_translation(self.position(i).to_vec()), material_id: b.material_id, emission }
                 })
         );
-        mesh.fill_instances_vbo(&instances);
-        Self { mesh, baubles }
+        self.mesh.fill_instances_vbo(&instances);
     }
 
-    fn gen_sphere(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, center: Point3<f32>, radius: f32, precision: u32) {
+    pub fn gen_sphere(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, center: Point3<f32>, radius: f32, precision: u32) {
         Self::gen_vertices(vertices, center, radius, precision);
         Self::gen_indices(indices, precision)
     }
@@ -172,11 +169,48 @@ impl Baubles {
 }
 
 impl Model for Baubles {
-    fn next_frame(&mut self) {
-        // nothing changes
+    fn name(&self) -> &'static str {
+        "baubles"
+    }
+
+    /// Baubles swing in the direction the wind blows at them, a bit further in gusts
+    fn blow(&mut self, wind: &Wind) {
+        self.swing_targets = self.baubles.iter()
+            .map(|b| {
+                let velocity = wind.velocity_at(b.center.into());
+                let swing = vec3(velocity.x, 0., velocity.z) * SWING_PER_WIND;
+                if swing.magnitude() > MAX_SWING { swing.normalize_to(MAX_SWING) } else { swing }
+            })
+            .collect();
     }
 
-    fn draw(&mut self, shader: &Shader) {
+    fn next_frame(&mut self, dt: f32) {
+        if self.swing_targets.len() != self.baubles.len() {
+            return;
+        }
+        self.swings.resize(self.baubles.len(), Vector3::zero());
+        let response = (SWING_RESPONSE * dt).min(1.);
+        for (swing, target) in self.swings.iter_mut().zip(&self.swing_targets) {
+            *swing += (target - *swing) * response;
+        }
+        self.fill_instances();
+    }
+
+    fn draw(&mut self, shader: &Shader) -> usize {
         self.mesh.draw_instances(shader, self.baubles.len());
+        1
+    }
+
+    fn colliders(&self) -> Vec<Collider> {
+        (0..self.baubles.len()).map(|i| Collider::Sphere { center: self.position(i), radius: self.radius }).collect()
+    }
+
+    fn pick(&self, ray: &Ray) -> Option<(usize, f32)> {
+        closest((0..self.baubles.len()).map(|i| ray.intersect_sphere(self.position(i), self.radius)))
+    }
+
+    fn highlight(&mut self, instance: Option<usize>) {
+        self.highlighted = instance;
+        self.fill_instances();
     }
 }
@@ -0,0 +1,94 @@
+use std::f32::consts::PI;
+
+use cgmath::{Basis2, Point3, Rad, vec2, vec3, Vector2};
+use cgmath::prelude::*;
+use rand::Rng;
+use rand::rngs::SmallRng;
+
+use crate::xmas_tree::mesh::Vertex;
+
+/// Snow crystals have six arms at that angle
+const ARM_ANGLE: f32 = PI / 3.;
+const MAX_SIDE_BRANCHES: usize = 3;
+
+/// Piece of a crystal arm, a thin rectangle along the segment
+struct Segment {
+    start: Vector2<f32>,
+    end: Vector2<f32>,
+    half_width: f32,
+}
+
+/// Flat snow crystal in the YZ plane facing +X, fitting in a circle of given radius.
+/// A hexagonal plate of random size with six identical arms, every arm is a spine with random pairs of side branches,
+/// so small plates with many branches make dendrites and large plates with few of them make hexagonal crystals.
+/// Triangles are counter-clockwise seen from +X, the back side has to be lit by flipping the normal.
+pub fn gen_crystal(radius: f32, rng: &mut SmallRng) -> (Vec<Vertex>, Vec<u32>) {
+    let half_width = radius * rng.gen_range(0.04, 0.1);
+    // ends of all segments stay that far from the center, so their corners do too
+    let reach = radius - half_width;
+    let plate_radius = reach * rng.gen_range(0.15, 0.6);
+
+    let mut arm = vec![Segment { start: vec2(0., 0.), end: vec2(reach, 0.), half_width }];
+    for _ in 0..rng.gen_range(0, MAX_SIDE_BRANCHES + 1) {
+        let distance = reach * rng.gen_range(0.25, 0.8);
+        // the longest branch still reaching only to the circle
+        let max_length = (-distance + (4. * reach * reach - 3. * distance * distance).sqrt()) / 2.;
+        let length = max_length * rng.gen_range(0.3, 1.);
+        let start = vec2(distance, 0.);
+        for side in &[-1., 1.] {
+            let direction = Basis2::from_angle(Rad(side * ARM_ANGLE)).rotate_vector(vec2(1., 0.));
+            arm.push(Segment { start, end: start + direction * length, half_width: half_width * 0.7 });
+        }
+    }
+
+    let normal = vec3(1., 0., 0.);
+    let vertex = |p: Vector2<f32>| Vertex { position: Point3::new(0., p.x, p.y), normal };
+    let mut vertices = vec![vertex(vec2(0., 0.))];
+    let mut indices = vec![];
+    // plate as a triangle fan
+    for i in 0..6 {
+        vertices.push(vertex(Basis2::from_angle(Rad(i as f32 * ARM_ANGLE)).rotate_vector(vec2(plate_radius, 0.))));
+        indices.extend_from_slice(&[0, 1 + i, 1 + (i + 1) % 6]);
+    }
+    for i in 0..6 {
+        let rotation = Basis2::from_angle(Rad(i as f32 * ARM_ANGLE));
+        for segment in &arm {
+            let along = (segment.end - segment.start).normalize();
+            let across = vec2(-along.y, along.x) * segment.half_width;
+            let first = vertices.len() as u32;
+            for corner in &[segment.start - across, segment.end - across, segment.end + across, segment.start + across] {
+                vertices.push(vertex(rotation.rotate_vector(*corner)));
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+    }
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{vec2, vec3};
+    use cgmath::prelude::*;
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+
+    use crate::xmas_tree::crystal::gen_crystal;
+
+    #[test]
+    fn crystals_fit_in_radius_and_face_x() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let (vertices, indices) = gen_crystal(0.5, &mut rng);
+            assert!(vertices.iter().all(|v| v.position.x == 0. && v.position.to_vec().magnitude() <= 0.5 + 1e-5));
+            assert!(vertices.iter().all(|v| v.normal == vec3(1., 0., 0.)));
+            for triangle in indices.chunks(3) {
+                let corner = |i: usize| {
+                    let p = vertices[triangle[i] as usize].position;
+                    vec2(p.y, p.z)
+                };
+                let (a, b) = (corner(1) - corner(0), corner(2) - corner(0));
+                assert!(a.perp_dot(b) > 0., "{:?}", triangle);
+            }
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use cgmath::{Deg, Euler, Matrix4, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{Projection, ProjectionKind};
+use crate::lights::{Attenuation, Light, LightKind};
+use crate::material::{Material, MAX_MATERIALS};
+use crate::wind::WindParameters;
+use crate::xmas_tree::fairy_lights::FairyLightsParameters;
+use crate::xmas_tree::snow::SnowParameters;
+use crate::xmas_tree::tree;
+
+pub const DEFAULT_SCENE: &str = "scenes/default.ron";
+
+/// Everything the scene consists of, loaded from a RON file, see scenes/default.ron
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    /// Seed used when none is given on the command line
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub camera: CameraDescription,
+    pub shaders: ShadersDescription,
+    pub lights: Vec<LightDescription>,
+    pub materials: Vec<MaterialDescription>,
+    pub models: Vec<ModelDescription>,
+    #[serde(default)]
+    pub wind: WindParameters,
+}
+
+/// Camera position is given in spherical coordinates, angles in radians.
+/// Field of view is vertical and in degrees, orthographic camera shows as much as the perspective one does at the look_at point.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub r: f32,
+    pub theta: f32,
+    pub phi: f32,
+    pub look_at: [f32; 3],
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default = "default_near")]
+    pub near: f32,
+    #[serde(default = "default_far")]
+    pub far: f32,
+    #[serde(default)]
+    pub orthographic: bool,
+}
+
+fn default_fov() -> f32 {
+    45.
+}
+
+fn default_near() -> f32 {
+    0.1
+}
+
+fn default_far() -> f32 {
+    100.
+}
+
+impl CameraDescription {
+    pub fn projection(&self) -> Projection {
+        let kind = if self.orthographic { ProjectionKind::Orthographic } else { ProjectionKind::Perspective };
+        Projection { kind, fov: Deg(self.fov), near: self.near, far: self.far }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShadersDescription {
+    pub vertex: String,
+    pub fragment: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDescription {
+    pub kind: LightKindDescription,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    /// Ignored by directional lights
+    #[serde(default)]
+    pub attenuation: Attenuation,
+    /// Distance the light reaches, without it the light reaches everywhere
+    #[serde(default)]
+    pub range: Option<f32>,
+    /// At most one light can cast shadows
+    #[serde(default)]
+    pub casts_shadow: bool,
+}
+
+/// Angles of spot lights are in degrees
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum LightKindDescription {
+    Directional { direction: [f32; 3] },
+    Point { position: [f32; 3] },
+    Spot { position: [f32; 3], direction: [f32; 3], inner_angle: f32, outer_angle: f32 },
+}
+
+impl LightDescription {
+    pub fn light(&self) -> Light {
+        let attenuation = self.attenuation;
+        let kind = match self.kind {
+            LightKindDescription::Directional { direction } => LightKind::Directional { direction: direction.into() },
+            LightKindDescription::Point { position } => LightKind::Point { position: Point3::from(position), attenuation },
+            LightKindDescription::Spot { position, direction, inner_angle, outer_angle } => LightKind::Spot {
+                position: Point3::from(position),
+                direction: direction.into(),
+                inner_angle: Deg(inner_angle),
+                outer_angle: Deg(outer_angle),
+                attenuation,
+            },
+        };
+        Light { kind, ambient: self.ambient.into(), diffuse: self.diffuse.into(), specular: self.specular.into(), range: self.range }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+}
+
+impl MaterialDescription {
+    pub fn material(&self) -> Material {
+        Material { ambient: self.ambient.into(), diffuse: self.diffuse.into(), specular: self.specular.into(), shininess: self.shininess }
+    }
+}
+
+/// Rotation is given as Euler angles in degrees, applied in order: scale, rotation, translation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform { translation: [0., 0., 0.], rotation: [0., 0., 0.], scale: [1., 1., 1.] }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let translation = Matrix4::from_translation(Vector3::from(self.translation));
+        let rotation = Matrix4::from(Euler { x: Deg(self.rotation[0]), y: Deg(self.rotation[1]), z: Deg(self.rotation[2]) });
+        let scale = Matrix4::from_nonuniform_scale(self.scale[0], self.scale[1], self.scale[2]);
+        translation * rotation * scale
+    }
+}
+
+/// Bauble position is given in cylindrical coordinates around the tree trunk, phi in radians
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BaubleDescription {
+    pub r: f32,
+    pub phi: f32,
+    pub h: f32,
+    pub material: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ModelDescription {
+    /// Square of size 2x2 lying on XZ plane, centered at (0, 0, 0) before transformation
+    Ground {
+        material: String,
+        #[serde(default)]
+        transform: Transform,
+    },
+    /// Model loaded from a Wavefront OBJ file, materials come from the accompanying MTL file
+    Tree {
+        path: String,
+        #[serde(default)]
+        transform: Transform,
+    },
+    Baubles {
+        radius: f32,
+        precision: u32,
+        baubles: Vec<BaubleDescription>,
+    },
+    Snow {
+        material: String,
+        parameters: SnowParameters,
+    },
+    /// Bulbs take the materials in turns and glow in their diffuse colour
+    FairyLights {
+        materials: Vec<String>,
+        parameters: FairyLightsParameters,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DescriptionError {
+    Io(String),
+    Parse(String),
+    /// entry points at the offending part of the description, e.g. models[2].baubles[5].material
+    Invalid { entry: String, message: String },
+}
+
+impl fmt::Display for DescriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DescriptionError::Io(message) => write!(f, "Failed to read scene description: {}", message),
+            DescriptionError::Parse(message) => write!(f, "Failed to parse scene description: {}", message),
+            DescriptionError::Invalid { entry, message } => write!(f, "Invalid scene description, {}: {}", entry, message),
+        }
+    }
+}
+
+fn invalid<T>(entry: String, message: &str) -> Result<T, DescriptionError> {
+    Err(DescriptionError::Invalid { entry, message: message.to_owned() })
+}
+
+impl SceneDescription {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DescriptionError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| DescriptionError::Io(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, DescriptionError> {
+        let description: SceneDescription = ron::de::from_str(source).map_err(|e| DescriptionError::Parse(e.to_string()))?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    fn validate(&self) -> Result<(), DescriptionError> {
+        if self.camera.r <= 0. {
+            return invalid("camera.r".to_owned(), "distance from the origin has to be positive");
+        }
+        if !(self.camera.fov > 0. && self.camera.fov < 180.) {
+            return invalid("camera.fov".to_owned(), "has to be between 0 and 180 degrees");
+        }
+        if !(self.camera.near > 0. && self.camera.near < self.camera.far) {
+            return invalid("camera.far".to_owned(), "clip planes have to satisfy 0 < near < far");
+        }
+        for (field, path) in &[("vertex", &self.shaders.vertex), ("fragment", &self.shaders.fragment)] {
+            if !Path::new(path).is_file() {
+                return invalid(format!("shaders.{}", field), &format!("file '{}' not found", path));
+            }
+        }
+        self.wind.validate().map_err(|(field, message)| DescriptionError::Invalid { entry: format!("wind.{}", field), message })?;
+        for (i, light) in self.lights.iter().enumerate() {
+            validate_colours(&format!("lights[{}]", i), &[("ambient", light.ambient), ("diffuse", light.diffuse), ("specular", light.specular)])?;
+            let entry = format!("lights[{}]", i);
+            match light.kind {
+                LightKindDescription::Directional { direction } | LightKindDescription::Spot { direction, .. } if direction == [0., 0., 0.] =>
+                    return invalid(entry + ".kind.direction", "cannot be zero"),
+                LightKindDescription::Spot { inner_angle, outer_angle, .. } if !(0. <= inner_angle && inner_angle <= outer_angle && outer_angle < 90.) =>
+                    return invalid(entry + ".kind.outer_angle", "angles have to satisfy 0 <= inner_angle <= outer_angle < 90"),
+                _ => {}
+            }
+            let attenuation = light.attenuation;
+            if attenuation.constant <= 0. || attenuation.linear < 0. || attenuation.quadratic < 0. {
+                return invalid(entry + ".attenuation", "constant has to be positive, other factors cannot be negative");
+            }
+            if light.range.is_some_and(|r| r <= 0.) {
+                return invalid(entry + ".range", "has to be positive");
+            }
+            if light.casts_shadow && self.lights[..i].iter().any(|l| l.casts_shadow) {
+                return invalid(format!("lights[{}].casts_shadow", i), "only one light can cast shadows");
+            }
+        }
+
+        let mut material_names: HashSet<&str> = HashSet::new();
+        for (i, material) in self.materials.iter().enumerate() {
+            let entry = format!("materials[{}]", i);
+            if !material_names.insert(&material.name) {
+                return invalid(entry + ".name", &format!("material '{}' is defined more than once", material.name));
+            }
+            validate_colours(&entry, &[("ambient", material.ambient), ("diffuse", material.diffuse), ("specular", material.specular)])?;
+            if material.shininess < 0. {
+                return invalid(entry + ".shininess", "cannot be negative");
+            }
+        }
+
+        // trees add materials from their MTL files
+        let mut loaded_materials = 0;
+        for (i, model) in self.models.iter().enumerate() {
+            let entry = format!("models[{}]", i);
+            let check_material = |entry: String, name: &str| {
+                if material_names.contains(name) { Ok(()) } else { invalid(entry, &format!("unknown material '{}'", name)) }
+            };
+            match model {
+                ModelDescription::Ground { material, transform } => {
+                    check_material(entry.clone() + ".material", material)?;
+                    validate_transform(&entry, transform)?;
+                }
+                ModelDescription::Tree { path, transform } => {
+                    if !Path::new(path).is_file() {
+                        return invalid(entry + ".path", &format!("file '{}' not found", path));
+                    }
+                    loaded_materials += tree::material_count(path).or_else(|e| invalid(entry.clone() + ".path", &e))?;
+                    validate_transform(&entry, transform)?;
+                }
+                ModelDescription::Baubles { radius, precision, baubles } => {
+                    if *radius <= 0. {
+                        return invalid(entry + ".radius", "has to be positive");
+                    }
+                    if *precision < 2 {
+                        return invalid(entry + ".precision", "has to be at least 2");
+                    }
+                    for (j, bauble) in baubles.iter().enumerate() {
+                        check_material(format!("{}.baubles[{}].material", entry, j), &bauble.material)?;
+                    }
+                }
+                ModelDescription::Snow { material, parameters } => {
+                    check_material(entry.clone() + ".material", material)?;
+                    parameters.validate().map_err(|(field, message)|
+                        DescriptionError::Invalid { entry: format!("{}.parameters.{}", entry, field), message })?;
+                }
+                ModelDescription::FairyLights { materials, parameters } => {
+                    if materials.is_empty() {
+                        return invalid(entry + ".materials", "there has to be at least one material");
+                    }
+                    for (j, material) in materials.iter().enumerate() {
+                        check_material(format!("{}.materials[{}]", entry, j), material)?;
+                    }
+                    parameters.validate().map_err(|(field, message)|
+                        DescriptionError::Invalid { entry: format!("{}.parameters.{}", entry, field), message })?;
+                }
+            }
+        }
+        if self.materials.len() + loaded_materials > MAX_MATERIALS {
+            return invalid("materials".to_owned(), &format!("at most {} materials are supported, {} are defined and the trees add {} more",
+                                                           MAX_MATERIALS, self.materials.len(), loaded_materials));
+        }
+        Ok(())
+    }
+}
+
+fn validate_colours(entry: &str, colours: &[(&str, [f32; 3])]) -> Result<(), DescriptionError> {
+    for (name, colour) in colours {
+        if colour.iter().any(|c| *c < 0.) {
+            return invalid(format!("{}.{}", entry, name), "colour components cannot be negative");
+        }
+    }
+    Ok(())
+}
+
+fn validate_transform(entry: &str, transform: &Transform) -> Result<(), DescriptionError> {
+    if transform.scale.contains(&0.) {
+        return invalid(format!("{}.transform.scale", entry), "scale cannot be zero");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use rstest::*;
+
+    use crate::material::MAX_MATERIALS;
+    use crate::xmas_tree::description::{DEFAULT_SCENE, DescriptionError, SceneDescription};
+
+    const MINIMAL: &str = r#"(
+        camera: (r: 18, theta: 1.7, phi: 0.9, look_at: (0, -1, 0)),
+        shaders: (vertex: "src/xmas_tree/shaders/static.vert", fragment: "src/xmas_tree/shaders/static.frag"),
+        lights: [(kind: Directional(direction: (-0.1, -1, -0.1)), ambient: (0.3, 0.3, 0.3), diffuse: (0.2, 0.2, 0.2), specular: (0, 0, 0))],
+        materials: [
+            (name: "red", ambient: (0.1, 0, 0), diffuse: (0.6, 0, 0), specular: (0.7, 0.6, 0.6), shininess: 76.8),
+            MATERIAL
+        ],
+        models: [
+            Ground(material: "red", transform: (translation: (0, -5, 0), scale: (10, 1, 10))),
+            Baubles(radius: 0.2, precision: 8, baubles: [(r: 0, phi: 0, h: 2.7, material: "red"), BAUBLE]),
+            MODEL
+        ],
+    )"#;
+
+    fn description(material: &str, bauble: &str, model: &str) -> String {
+        MINIMAL.replace("MATERIAL", material).replace("BAUBLE", bauble).replace("MODEL", model)
+    }
+
+    fn fairy_lights(materials: &str, pattern: &str, emitted_lights: usize) -> String {
+        format!("FairyLights(materials: [{}], parameters: (count: 50, bottom_radius: 4, bottom_height: -4, top_radius: 0.5, top_height: 2.5, \
+                 turns: 5, bulb_radius: 0.05, pattern: {}, emitted_lights: {}))", materials, pattern, emitted_lights)
+    }
+
+    #[test]
+    fn default_scene_is_valid() {
+        let result = SceneDescription::load(DEFAULT_SCENE);
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[test]
+    fn minimal_scene_is_valid() {
+        let result = SceneDescription::parse(&description("", "", ""));
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[rstest(material, bauble, model, expected_entry,
+    case(r#"(name: "red", ambient: (0, 0, 0), diffuse: (0, 0, 0), specular: (0, 0, 0), shininess: 1)"#, "", "", "materials[1].name"),
+    case(r#"(name: "blue", ambient: (0, -1, 0), diffuse: (0, 0, 0), specular: (0, 0, 0), shininess: 1)"#, "", "", "materials[1].ambient"),
+    case("", r#"(r: 1, phi: 0, h: 1, material: "pink")"#, "", "models[1].baubles[1].material"),
+    case("", "", r#"Tree(path: "missing.obj")"#, "models[2].path"),
+    case("", "", r#"Ground(material: "red", transform: (scale: (1, 0, 1)))"#, "models[2].transform.scale"),
+    case("", "", r#"Snow(material: "red", parameters: (count: 0, min: (-1, -1, -1), max: (1, 1, 1), fall_velocity: 1, max_random_offset: 1, max_random_rotation: 1))"#, "models[2].parameters.count"),
+    case("", "", r#"Snow(material: "red", parameters: (count: 10, min: (-1, 1, -1), max: (1, 1, 1), fall_velocity: 1, max_random_offset: 1, max_random_rotation: 1))"#, "models[2].parameters.max"),
+    case("", "", r#"Snow(material: "red", parameters: (count: 10, min: (-1, -1, -1), max: (1, 1, 1), fall_velocity: 1, max_random_offset: -1, max_random_rotation: 1))"#, "models[2].parameters.max_random_offset"),
+    case("", "", r#"Snow(material: "red", parameters: (count: 10, min: (-1, -1, -1), max: (1, 1, 1), fall_velocity: 1, max_random_offset: 1, max_random_rotation: 1, min_scale: 2, max_scale: 1))"#, "models[2].parameters.max_scale"),
+    case("", "", &fairy_lights(r#""red", "green""#, "Fade(period: 2)", 0), "models[2].materials[1]"),
+    case("", "", &fairy_lights(r#""red""#, "Chase(speed: 0, length: 3)", 0), "models[2].parameters.pattern"),
+    case("", "", &fairy_lights(r#""red""#, "Twinkle(speed: 1)", 60), "models[2].parameters.emitted_lights"),
+    case("", "", &fairy_lights(r#""red""#, "Fade(period: 2)", 0).replace("top_radius: 0.5", "top_radius: -0.5"), "models[2].parameters.top_radius"),
+    )]
+    fn points_at_invalid_entry(material: &str, bauble: &str, model: &str, expected_entry: &str) {
+        match SceneDescription::parse(&description(material, bauble, model)) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, expected_entry),
+            result => panic!("Expected validation error for {}, got {:?}", expected_entry, result),
+        }
+    }
+
+    #[test]
+    fn counts_tree_materials_towards_limit() {
+        let materials: Vec<String> = (0..MAX_MATERIALS - 2)
+            .map(|i| format!(r#"(name: "m{}", ambient: (0, 0, 0), diffuse: (0, 0, 0), specular: (0, 0, 0), shininess: 1)"#, i))
+            .collect();
+        let source = description(&materials.join(", "), "", r#"Tree(path: "models/tree.obj")"#);
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, "materials"),
+            result => panic!("Expected validation error, got {:?}", result),
+        }
+        assert!(SceneDescription::parse(&description(&materials[1..].join(", "), "", "")).is_ok());
+    }
+
+    #[test]
+    fn points_at_missing_shader() {
+        let source = description("", "", "").replace("static.frag", "missing.frag");
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, "shaders.fragment"),
+            result => panic!("Expected validation error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn requires_materials_for_all_tree_meshes() {
+        let path = env::temp_dir().join("xmas_tree_without_materials.obj");
+        fs::write(&path, "o tree\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let source = description("", "", &format!("Tree(path: {:?})", path));
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, "models[2].path"),
+            result => panic!("Expected validation error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn allows_only_one_shadow_casting_light() {
+        let light = "(kind: Point(position: (5, 6, 2)), ambient: (0, 0, 0), diffuse: (1, 1, 1), specular: (0, 0, 0), casts_shadow: true)";
+        let source = description("", "", "").replace("lights: [", &format!("lights: [{}, {}, ", light, light));
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, "lights[1].casts_shadow"),
+            result => panic!("Expected validation error, got {:?}", result),
+        }
+    }
+
+    #[rstest(camera, expected_entry,
+    case("fov: 180", "camera.fov"),
+    case("near: 10, far: 5", "camera.far"),
+    case("near: 0", "camera.far"),
+    )]
+    fn points_at_invalid_camera(camera: &str, expected_entry: &str) {
+        let source = description("", "", "").replace("look_at: (0, -1, 0)", &format!("look_at: (0, -1, 0), {}", camera));
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, expected_entry),
+            result => panic!("Expected validation error for {}, got {:?}", expected_entry, result),
+        }
+    }
+
+    #[rstest(wind, expected_entry,
+    case("strength: -1", "wind.strength"),
+    case("gustiness: 1.5", "wind.gustiness"),
+    )]
+    fn points_at_invalid_wind(wind: &str, expected_entry: &str) {
+        let source = description("", "", "").replace("models: [", &format!("wind: ({}),\n        models: [", wind));
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, expected_entry),
+            result => panic!("Expected validation error for {}, got {:?}", expected_entry, result),
+        }
+    }
+
+    #[rstest(kind, attenuation, expected_entry,
+    case("Directional(direction: (0, 0, 0))", "(constant: 1, linear: 0, quadratic: 0)", "lights[1].kind.direction"),
+    case("Spot(position: (0, 5, 0), direction: (0, -1, 0), inner_angle: 30, outer_angle: 20)", "(constant: 1, linear: 0, quadratic: 0)", "lights[1].kind.outer_angle"),
+    case("Point(position: (0, 5, 0))", "(constant: 0, linear: 0.1, quadratic: 0)", "lights[1].attenuation"),
+    )]
+    fn points_at_invalid_light(kind: &str, attenuation: &str, expected_entry: &str) {
+        let light = format!("(kind: {}, ambient: (0, 0, 0), diffuse: (1, 1, 1), specular: (0, 0, 0), attenuation: {})", kind, attenuation);
+        let source = description("", "", "").replace("specular: (0, 0, 0))],", &format!("specular: (0, 0, 0)), {}],", light));
+        match SceneDescription::parse(&source) {
+            Err(DescriptionError::Invalid { entry, .. }) => assert_eq!(entry, expected_entry),
+            result => panic!("Expected validation error for {}, got {:?}", expected_entry, result),
+        }
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let result = SceneDescription::parse(&description("", "", "Sphere(radius: 1)"));
+        assert!(matches!(result, Err(DescriptionError::Parse(_))), "{:?}", result);
+    }
+}
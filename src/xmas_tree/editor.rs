@@ -0,0 +1,207 @@
+use std::fs;
+
+use cgmath::Point3;
+use ron::ser::PrettyConfig;
+
+use crate::coords::CylindricalPoint3;
+use crate::material::MaterialId;
+use crate::picking::Ray;
+use crate::xmas_tree::baubles::Bauble;
+use crate::xmas_tree::description::BaubleDescription;
+use crate::xmas_tree::scene::Scene;
+
+/// Edit mode for decorations: clicking on the tree adds a bauble, dragging moves it,
+/// the bauble under the cursor can get the next material or be removed.
+/// New baubles get the material last given to a bauble, cycling materials with no bauble under the cursor picks it.
+/// Layout of the baubles can be saved to and loaded from a RON file with the same entries as in scene description.
+pub struct Editor {
+    pub enabled: bool,
+    layout_path: String,
+    /// index of the bauble being moved
+    dragged: Option<usize>,
+    /// material of new baubles, the one of the last bauble in the scene until chosen
+    material: Option<MaterialId>,
+}
+
+impl Editor {
+    pub fn new(layout_path: &str) -> Self {
+        Editor { enabled: false, layout_path: layout_path.to_owned(), dragged: None, material: None }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.dragged = None;
+        println!("Edit mode {}", if self.enabled { "on" } else { "off" });
+    }
+
+    /// Starts dragging the bauble under the cursor or adds a new one where the ray hits the tree
+    pub fn press(&mut self, scene: &mut Scene, ray: &Ray) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(index) = scene.hovered_bauble() {
+            self.dragged = Some(index);
+            return;
+        }
+        let center = match Self::center_on_tree(scene, ray) {
+            Some(center) => center,
+            None => return,
+        };
+        if let Some(material_id) = self.new_bauble_material(scene) {
+            if let Some(baubles) = scene.baubles_mut() {
+                self.dragged = Some(baubles.add(Bauble { center, material_id }));
+            }
+        }
+    }
+
+    fn new_bauble_material(&self, scene: &mut Scene) -> Option<MaterialId> {
+        let first = scene.materials().first().map(|(_, id)| *id);
+        self.material.or_else(|| scene.baubles_mut()?.baubles().last().map(|b| b.material_id)).or(first)
+    }
+
+    pub fn drag(&mut self, scene: &mut Scene, ray: &Ray) {
+        if let (Some(index), Some(center)) = (self.dragged, Self::center_on_tree(scene, ray)) {
+            if let Some(baubles) = scene.baubles_mut() {
+                baubles.move_to(index, center);
+            }
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragged.is_some()
+    }
+
+    pub fn release(&mut self) {
+        self.dragged = None;
+    }
+
+    /// Bauble sticking out of the tree where the ray hits it
+    fn center_on_tree(scene: &mut Scene, ray: &Ray) -> Option<CylindricalPoint3<f32>> {
+        let hit = scene.pick_tree(ray)?;
+        let radius = scene.baubles_mut()?.radius();
+        let center: Point3<f32> = hit - ray.direction * radius;
+        Some(CylindricalPoint3::from(center))
+    }
+
+    /// Gives the bauble under the cursor the next material of the scene, new baubles get it too.
+    /// Without a bauble under the cursor only new baubles get the next material.
+    pub fn cycle_material(&mut self, scene: &mut Scene) {
+        if !self.enabled {
+            return;
+        }
+        let materials = scene.materials().to_vec();
+        let hovered = scene.hovered_bauble();
+        let current = match (hovered, scene.baubles_mut()) {
+            (Some(index), Some(baubles)) => Some(baubles.baubles()[index].material_id),
+            _ => self.new_bauble_material(scene),
+        };
+        let next = match current.and_then(|current| next_material(&materials, current)) {
+            Some(next) => next,
+            None => return,
+        };
+        self.material = Some(next.1);
+        match (hovered, scene.baubles_mut()) {
+            (Some(index), Some(baubles)) => baubles.set_material(index, next.1),
+            _ => println!("New baubles are {}", next.0),
+        }
+    }
+
+    pub fn remove(&mut self, scene: &mut Scene) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(index) = scene.hovered_bauble() {
+            scene.clear_hover();
+            self.dragged = None;
+            if let Some(baubles) = scene.baubles_mut() {
+                baubles.remove(index);
+            }
+        }
+    }
+
+    pub fn save(&self, scene: &mut Scene) -> Result<(), String> {
+        let materials = scene.materials().to_vec();
+        let baubles = scene.baubles_mut().ok_or("There are no baubles in the scene")?;
+        let source = save_layout(baubles.baubles(), &materials)?;
+        fs::write(&self.layout_path, source).map_err(|e| format!("{}: {}", self.layout_path, e))?;
+        println!("Saved {} baubles to {}", baubles.baubles().len(), self.layout_path);
+        Ok(())
+    }
+
+    pub fn load(&mut self, scene: &mut Scene) -> Result<(), String> {
+        let source = fs::read_to_string(&self.layout_path).map_err(|e| format!("{}: {}", self.layout_path, e))?;
+        let baubles = load_layout(&source, scene.materials()).map_err(|e| format!("{}: {}", self.layout_path, e))?;
+        scene.clear_hover();
+        self.dragged = None;
+        let count = baubles.len();
+        scene.baubles_mut().ok_or("There are no baubles in the scene")?.replace_all(baubles);
+        println!("Loaded {} baubles from {}", count, self.layout_path);
+        Ok(())
+    }
+}
+
+/// Material following the given one in the scene's order, None when the scene has no materials
+fn next_material(materials: &[(String, MaterialId)], current: MaterialId) -> Option<&(String, MaterialId)> {
+    let position = materials.iter().position(|(_, id)| *id == current);
+    materials.get(position.map_or(0, |p| (p + 1) % materials.len()))
+}
+
+/// Layout in RON, materials are referred to by their names
+fn save_layout(baubles: &[Bauble], materials: &[(String, MaterialId)]) -> Result<String, String> {
+    let layout: Vec<BaubleDescription> = baubles.iter()
+        .map(|b| {
+            let material = materials.iter().find(|(_, id)| *id == b.material_id).map_or("", |(name, _)| name);
+            BaubleDescription { r: b.center.r, phi: b.center.phi, h: b.center.h, material: material.to_owned() }
+        })
+        .collect();
+    ron::ser::to_string_pretty(&layout, PrettyConfig::default()).map_err(|e| e.to_string())
+}
+
+/// Baubles of a layout saved by save_layout(), all their materials have to exist in the scene
+fn load_layout(source: &str, materials: &[(String, MaterialId)]) -> Result<Vec<Bauble>, String> {
+    let layout: Vec<BaubleDescription> = ron::de::from_str(source).map_err(|e| e.to_string())?;
+    layout.iter().enumerate()
+        .map(|(i, b)| {
+            let material_id = materials.iter().find(|(name, _)| *name == b.material).map(|(_, id)| *id)
+                .ok_or(format!("bauble {} has unknown material '{}'", i, b.material))?;
+            Ok(Bauble { center: CylindricalPoint3::new(b.r, b.phi, b.h), material_id })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coords::CylindricalPoint3;
+    use crate::material::MaterialId;
+    use crate::xmas_tree::baubles::Bauble;
+    use crate::xmas_tree::editor::{load_layout, next_material, save_layout};
+
+    fn materials() -> Vec<(String, MaterialId)> {
+        vec![("gold".to_owned(), 0.), ("red".to_owned(), 1.), ("silver".to_owned(), 2.)]
+    }
+
+    #[test]
+    fn loads_saved_layout() {
+        let baubles = vec![
+            Bauble { center: CylindricalPoint3::new(1.5, 0.25, -2.), material_id: 1. },
+            Bauble { center: CylindricalPoint3::new(0.75, 3.5, 1.25), material_id: 2. },
+        ];
+        let source = save_layout(&baubles, &materials()).unwrap();
+        assert_eq!(load_layout(&source, &materials()), Ok(baubles));
+    }
+
+    #[test]
+    fn rejects_unknown_material() {
+        let source = r#"[(r: 1, phi: 0, h: 0, material: "red"), (r: 1, phi: 0, h: 0, material: "blue")]"#;
+        assert_eq!(load_layout(source, &materials()), Err("bauble 1 has unknown material 'blue'".to_owned()));
+    }
+
+    #[test]
+    fn cycles_through_materials() {
+        let materials = materials();
+        assert_eq!(next_material(&materials, 1.).map(|m| m.1), Some(2.));
+        assert_eq!(next_material(&materials, 2.).map(|m| m.1), Some(0.));
+        assert_eq!(next_material(&materials, 7.).map(|m| m.1), Some(0.));
+        assert_eq!(next_material(&[], 0.), None);
+    }
+}
@@ -0,0 +1,319 @@
+use core::f32::consts::PI;
+
+use cgmath::{Matrix4, Point3, Vector3};
+use cgmath::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use serde::Deserialize;
+
+use crate::coords::CylindricalPoint3;
+use crate::lights::{Attenuation, Light, LightKind};
+use crate::material::MaterialId;
+use crate::model::{Instance, Model};
+use crate::shader::Shader;
+use crate::xmas_tree::baubles::Baubles;
+use crate::xmas_tree::mesh::{Mesh, Vertex};
+
+/// String of bulbs wound around the tree along a spiral going from the bottom of the cone to its top
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FairyLightsParameters {
+    pub count: usize,
+    pub bottom_radius: f32,
+    pub bottom_height: f32,
+    pub top_radius: f32,
+    pub top_height: f32,
+    /// how many times the string goes around the tree
+    pub turns: f32,
+    pub bulb_radius: f32,
+    pub pattern: PatternDescription,
+    /// number of bulbs, spread evenly along the string, that also light up the scene
+    #[serde(default)]
+    pub emitted_lights: usize,
+    #[serde(default = "default_light_strength")]
+    pub light_strength: f32,
+    /// how far the light of a single bulb reaches
+    #[serde(default = "default_light_range")]
+    pub light_range: f32,
+}
+
+fn default_light_strength() -> f32 {
+    0.5
+}
+
+fn default_light_range() -> f32 {
+    2.
+}
+
+/// Built-in animations, speeds are per second
+#[derive(Debug, Clone, Deserialize)]
+pub enum PatternDescription {
+    /// every bulb pulses on its own with a random phase
+    Twinkle { speed: f32 },
+    /// a group of `length` lit bulbs runs along the string
+    Chase { speed: f32, length: f32 },
+    /// all bulbs fade in and out together
+    Fade { period: f32 },
+    /// every `interval` seconds each bulb is randomly switched on or off
+    Random { interval: f32 },
+}
+
+impl FairyLightsParameters {
+    /// Returns name of the invalid field together with the reason
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if self.count == 0 {
+            return Err(("count", "there has to be at least one bulb".to_owned()));
+        }
+        if self.bulb_radius <= 0. {
+            return Err(("bulb_radius", "has to be positive".to_owned()));
+        }
+        if self.bottom_radius < 0. {
+            return Err(("bottom_radius", "cannot be negative".to_owned()));
+        }
+        if self.top_radius < 0. {
+            return Err(("top_radius", "cannot be negative".to_owned()));
+        }
+        if self.top_height <= self.bottom_height {
+            return Err(("top_height", "has to be greater than bottom_height".to_owned()));
+        }
+        if self.turns <= 0. {
+            return Err(("turns", "has to be positive".to_owned()));
+        }
+        if self.light_range <= 0. {
+            return Err(("light_range", "has to be positive".to_owned()));
+        }
+        if self.emitted_lights > self.count {
+            return Err(("emitted_lights", "cannot be greater than the number of bulbs".to_owned()));
+        }
+        let speed_valid = match self.pattern {
+            PatternDescription::Twinkle { speed } => speed > 0.,
+            PatternDescription::Chase { speed, length } => speed > 0. && length > 0.,
+            PatternDescription::Fade { period } => period > 0.,
+            PatternDescription::Random { interval } => interval > 0.,
+        };
+        if !speed_valid {
+            return Err(("pattern", "all pattern parameters have to be positive".to_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn pattern(&self, seed: u64) -> Box<dyn LightPattern> {
+        match self.pattern {
+            PatternDescription::Twinkle { speed } => Box::new(Twinkle::new(self.count, speed, seed)),
+            PatternDescription::Chase { speed, length } => Box::new(Chase { speed, length }),
+            PatternDescription::Fade { period } => Box::new(Fade { period }),
+            PatternDescription::Random { interval } => Box::new(RandomBlink::new(self.count, interval, seed)),
+        }
+    }
+
+    fn bulb_position(&self, bulb: usize) -> Point3<f32> {
+        let t = if self.count > 1 { bulb as f32 / (self.count - 1) as f32 } else { 0. };
+        let r = self.bottom_radius + t * (self.top_radius - self.bottom_radius);
+        let h = self.bottom_height + t * (self.top_height - self.bottom_height);
+        CylindricalPoint3::new(r, t * self.turns * 2. * PI, h).into()
+    }
+}
+
+/// Animation of the bulbs, implement it to make the lights blink in your own way
+pub trait LightPattern {
+    /// Called once per frame before asking for brightness of the bulbs
+    fn advance(&mut self, _dt: f32) {}
+
+    /// Brightness of given bulb from 0 (off) to 1 (fully lit), time is in seconds since the lights were switched on
+    fn brightness(&self, bulb: usize, bulbs: usize, time: f32) -> f32;
+}
+
+pub struct Twinkle {
+    speed: f32,
+    phases: Vec<f32>,
+}
+
+impl Twinkle {
+    pub fn new(bulbs: usize, speed: f32, seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let phases = (0..bulbs).map(|_| rng.gen_range(0., 2. * PI)).collect();
+        Twinkle { speed, phases }
+    }
+}
+
+impl LightPattern for Twinkle {
+    fn brightness(&self, bulb: usize, _bulbs: usize, time: f32) -> f32 {
+        let wave = 0.5 + 0.5 * (2. * PI * self.speed * time + self.phases[bulb]).sin();
+        // bulbs stay dim most of the time and flash only briefly
+        wave.powi(3)
+    }
+}
+
+pub struct Chase {
+    pub speed: f32,
+    pub length: f32,
+}
+
+impl LightPattern for Chase {
+    fn brightness(&self, bulb: usize, bulbs: usize, time: f32) -> f32 {
+        let head = (self.speed * time) % bulbs as f32;
+        let distance = (head - bulb as f32).rem_euclid(bulbs as f32);
+        (1. - distance / self.length).max(0.)
+    }
+}
+
+pub struct Fade {
+    pub period: f32,
+}
+
+impl LightPattern for Fade {
+    fn brightness(&self, _bulb: usize, _bulbs: usize, time: f32) -> f32 {
+        0.5 - 0.5 * (2. * PI * time / self.period).cos()
+    }
+}
+
+pub struct RandomBlink {
+    interval: f32,
+    elapsed: f32,
+    lit: Vec<bool>,
+    rng: SmallRng,
+}
+
+impl RandomBlink {
+    pub fn new(bulbs: usize, interval: f32, seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let lit = (0..bulbs).map(|_| rng.gen()).collect();
+        RandomBlink { interval, elapsed: 0., lit, rng }
+    }
+}
+
+impl LightPattern for RandomBlink {
+    fn advance(&mut self, dt: f32) {
+        self.elapsed += dt;
+        while self.elapsed >= self.interval {
+            self.elapsed -= self.interval;
+            let rng = &mut self.rng;
+            self.lit.iter_mut().for_each(|l| *l = rng.gen());
+        }
+    }
+
+    fn brightness(&self, bulb: usize, _bulbs: usize, _time: f32) -> f32 {
+        if self.lit[bulb] { 1. } else { 0. }
+    }
+}
+
+struct Bulb {
+    position: Point3<f32>,
+    material_id: MaterialId,
+    colour: Vector3<f32>,
+    brightness: f32,
+}
+
+pub struct FairyLights {
+    mesh: Mesh,
+    bulbs: Vec<Bulb>,
+    pattern: Box<dyn LightPattern>,
+    /// indices of bulbs shining on the rest of the scene
+    emitting_bulbs: Vec<usize>,
+    light_strength: f32,
+    light_range: f32,
+    time: f32,
+}
+
+impl FairyLights {
+    /// Bulbs take materials in turns, colour of a material is the colour of the light the bulb emits
+    pub fn new(materials: &[(MaterialId, Vector3<f32>)], parameters: &FairyLightsParameters, pattern: Box<dyn LightPattern>) -> Self {
+        let precision = 4;
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(2 * precision * precision);
+        let mut indices: Vec<u32> = Vec::with_capacity(3 * 4 * precision * precision);
+        Baubles::gen_sphere(&mut vertices, &mut indices, Point3::new(0., 0., 0.), parameters.bulb_radius, precision as u32);
+        let mesh = Mesh::new(vertices, indices, parameters.count);
+
+        let bulbs = (0..parameters.count)
+            .map(|i| {
+                let (material_id, colour) = materials[i % materials.len()];
+                Bulb { position: parameters.bulb_position(i), material_id, colour, brightness: 0. }
+            })
+            .collect();
+        let emitting_bulbs = (0..parameters.emitted_lights).map(|i| i * parameters.count / parameters.emitted_lights).collect();
+        let mut fairy_lights = Self { mesh, bulbs, pattern, emitting_bulbs, light_strength: parameters.light_strength, light_range: parameters.light_range, time: 0. };
+        fairy_lights.update_bulbs();
+        fairy_lights
+    }
+
+    fn update_bulbs(&mut self) {
+        let bulbs_no = self.bulbs.len();
+        for (i, bulb) in self.bulbs.iter_mut().enumerate() {
+            bulb.brightness = self.pattern.brightness(i, bulbs_no, self.time).clamp(0., 1.);
+        }
+        let instances: Vec<Instance> = self.bulbs.iter()
+            .map(|b| Instance { model: Matrix4::from_translation(b.position.to_vec()), material_id: b.material_id, emission: b.brightness })
+            .collect();
+        self.mesh.fill_instances_vbo(&instances);
+    }
+}
+
+impl Model for FairyLights {
+    fn name(&self) -> &'static str {
+        "fairy lights"
+    }
+
+    fn next_frame(&mut self, dt: f32) {
+        self.time += dt;
+        self.pattern.advance(dt);
+        self.update_bulbs();
+    }
+
+    fn draw(&mut self, shader: &Shader) -> usize {
+        self.mesh.draw_instances(shader, self.bulbs.len());
+        1
+    }
+
+    fn emitted_lights(&self) -> Vec<Light> {
+        self.emitting_bulbs.iter()
+            .map(|i| {
+                let bulb = &self.bulbs[*i];
+                let diffuse = bulb.colour * bulb.brightness * self.light_strength;
+                Light {
+                    kind: LightKind::Point { position: bulb.position, attenuation: Attenuation::NONE },
+                    ambient: Vector3::new(0., 0., 0.),
+                    diffuse,
+                    specular: diffuse * 0.5,
+                    range: Some(self.light_range),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use crate::xmas_tree::fairy_lights::{Chase, Fade, LightPattern, RandomBlink, Twinkle};
+
+    #[rstest(bulb, time, expected,
+    case(0, 0., 1.),
+    case(1, 0., 0.),
+    case(2, 1., 1.),
+    case(1, 1., 0.5),
+    case(0, 0.5, 0.5),
+    )]
+    fn chase_runs_along_the_string(bulb: usize, time: f32, expected: f32) {
+        let chase = Chase { speed: 2., length: 2. };
+        assert!((chase.brightness(bulb, 10, time) - expected).abs() < 1e-5, "got {}", chase.brightness(bulb, 10, time));
+    }
+
+    #[test]
+    fn fade_goes_from_dark_to_lit() {
+        let fade = Fade { period: 2. };
+        assert!(fade.brightness(0, 1, 0.) < 1e-5);
+        assert!((fade.brightness(0, 1, 1.) - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn random_patterns_are_reproducible() {
+        let twinkles = (Twinkle::new(20, 1., 7), Twinkle::new(20, 1., 7));
+        assert!((0..20).all(|i| twinkles.0.brightness(i, 20, 0.3) == twinkles.1.brightness(i, 20, 0.3)));
+
+        let (mut first, mut second) = (RandomBlink::new(20, 0.5, 7), RandomBlink::new(20, 0.5, 7));
+        first.advance(1.2);
+        second.advance(1.2);
+        assert!((0..20).all(|i| first.brightness(i, 20, 1.2) == second.brightness(i, 20, 1.2)));
+    }
+}
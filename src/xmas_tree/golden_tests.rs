@@ -0,0 +1,151 @@
+//! Golden-image regression tests for the scene.
+//!
+//! Each case renders a part of the default scene with its fixed camera and a fixed snow seed into an offscreen framebuffer
+//! and compares it pixel by pixel with a reference image stored in `src/xmas_tree/golden`.
+//! On a mismatch both the actual picture and an image highlighting differing pixels are written to `target/golden`.
+//!
+//! The tests need an OpenGL 3.3 context, so they're ignored by default. A software renderer is enough, e.g.
+//! `LIBGL_ALWAYS_SOFTWARE=1 xvfb-run cargo test -- --ignored`. The reference images were rendered by Mesa llvmpipe.
+//! A missing reference image is a failure, run with `UPDATE_GOLDEN=1` to record all of them after an intended change.
+
+use std::env;
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::capture::save_png;
+use crate::offscreen::Framebuffer;
+use crate::options::Options;
+use crate::xmas_tree::description::{DEFAULT_SCENE, ModelDescription, SceneDescription};
+use crate::xmas_tree::scene::Scene;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const SNOW_SEED: u64 = 2020;
+/// Maximum difference of a single colour channel for a pixel to be still considered equal
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Maximum fraction of pixels that may differ, rasterization differs a bit between drivers
+const MAX_MISMATCHED_FRACTION: f32 = 0.001;
+
+const REFERENCE_DIR: &str = "src/xmas_tree/golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+/// Name of the reference image and which models from the default scene it shows
+type Case = (&'static str, fn(&ModelDescription) -> bool);
+
+struct Comparison {
+    mismatched: usize,
+    diff: Vec<u8>,
+}
+
+/// Compares two RGBA images of the same size, differing pixels are marked red in the diff image, all others are greyed out
+fn compare(expected: &[u8], actual: &[u8], tolerance: u8) -> Comparison {
+    let mut mismatched = 0;
+    let mut diff: Vec<u8> = Vec::with_capacity(actual.len());
+    for (e, a) in expected.chunks(4).zip(actual.chunks(4)) {
+        let differs = e.iter().zip(a.iter()).any(|(ec, ac)| (*ec as i16 - *ac as i16).abs() > tolerance as i16);
+        if differs {
+            mismatched += 1;
+            diff.extend([255, 0, 0, 255].iter());
+        } else {
+            let grey = ((a[0] as u16 + a[1] as u16 + a[2] as u16) / 12) as u8;
+            diff.extend([grey, grey, grey, 255].iter());
+        }
+    }
+    Comparison { mismatched, diff }
+}
+
+fn load_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    (info.width, info.height, pixels)
+}
+
+fn check_golden(name: &str, pixels: &[u8]) -> Result<(), String> {
+    let reference = Path::new(REFERENCE_DIR).join(format!("{}.png", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(REFERENCE_DIR).unwrap();
+        save_png(&reference, WIDTH, HEIGHT, pixels).unwrap();
+        println!("Recorded reference image {:?}", reference);
+        return Ok(());
+    }
+    if !reference.exists() {
+        return Err(format!("{}: reference image {:?} is missing, record it with UPDATE_GOLDEN=1", name, reference));
+    }
+
+    let (width, height, expected) = load_png(&reference);
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(format!("{}: reference is {}x{}, rendered {}x{}", name, width, height, WIDTH, HEIGHT));
+    }
+    let comparison = compare(&expected, pixels, CHANNEL_TOLERANCE);
+    let max_mismatched = (MAX_MISMATCHED_FRACTION * (WIDTH * HEIGHT) as f32) as usize;
+    if comparison.mismatched > max_mismatched {
+        fs::create_dir_all(OUTPUT_DIR).unwrap();
+        let actual_path = Path::new(OUTPUT_DIR).join(format!("{}.actual.png", name));
+        let diff_path = Path::new(OUTPUT_DIR).join(format!("{}.diff.png", name));
+        save_png(&actual_path, WIDTH, HEIGHT, pixels).unwrap();
+        save_png(&diff_path, WIDTH, HEIGHT, &comparison.diff).unwrap();
+        return Err(format!("{}: {} pixels differ (at most {} allowed), see {:?} and {:?}",
+                           name, comparison.mismatched, max_mismatched, actual_path, diff_path));
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn scene_matches_golden_images() {
+    // GLFW can be initialized only once per process, that's why all cases are rendered in a single test
+    let options = Options { headless: true, width: WIDTH, height: HEIGHT, ..Options::default() };
+    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+    let (mut window, _events) = crate::setup_window(&mut glfw, &options);
+    crate::setup_gl(&mut window);
+    let framebuffer = Framebuffer::new(WIDTH, HEIGHT, 4);
+
+    let full_description = SceneDescription::load(DEFAULT_SCENE).unwrap();
+    let cases: [Case; 6] = [
+        ("ground", |m| matches!(m, ModelDescription::Ground { .. })),
+        ("tree", |m| matches!(m, ModelDescription::Tree { .. })),
+        ("baubles", |m| matches!(m, ModelDescription::Baubles { .. })),
+        ("snow", |m| matches!(m, ModelDescription::Snow { .. })),
+        ("fairy_lights", |m| matches!(m, ModelDescription::FairyLights { .. })),
+        ("scene", |_| true),
+    ];
+    let mut failures: Vec<String> = vec![];
+    for (name, included) in cases.iter() {
+        let mut description = full_description.clone();
+        description.models.retain(included);
+        let mut scene = Scene::setup(&window, &description, SNOW_SEED);
+        framebuffer.bind();
+        scene.draw();
+        if let Err(failure) = check_golden(name, &framebuffer.read_pixels()) {
+            failures.push(failure);
+        }
+    }
+    framebuffer.unbind();
+    assert!(failures.is_empty(), "Rendered images differ from references:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn identical_images_match() {
+    let image = vec![10, 20, 30, 255, 40, 50, 60, 255];
+    let comparison = compare(&image, &image, 0);
+    assert_eq!(comparison.mismatched, 0);
+}
+
+#[test]
+fn differences_within_tolerance_match() {
+    let expected = vec![10, 20, 30, 255, 40, 50, 60, 255];
+    let actual = vec![12, 18, 30, 255, 40, 50, 64, 255];
+    assert_eq!(compare(&expected, &actual, 4).mismatched, 0);
+    assert_eq!(compare(&expected, &actual, 3).mismatched, 1);
+}
+
+#[test]
+fn differing_pixels_are_marked_in_diff() {
+    let expected = vec![0, 0, 0, 255, 0, 0, 0, 255];
+    let actual = vec![0, 0, 0, 255, 255, 255, 255, 255];
+    let comparison = compare(&expected, &actual, 0);
+    assert_eq!(comparison.mismatched, 1);
+    assert_eq!(&comparison.diff[4..8], &[255, 0, 0, 255]);
+}
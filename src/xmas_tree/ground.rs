@@ -1,21 +1,26 @@
-use cgmath::{Matrix4, Point3, SquareMatrix, vec3, Vector3};
+use cgmath::{Matrix4, Point3, vec3};
+use cgmath::prelude::*;
 
-use crate::material::{Material, Materials};
-use crate::model::{Instance, Model};
+use crate::material::MaterialId;
+use crate::model::{HIGHLIGHT_EMISSION, Instance, Model};
+use crate::picking::Ray;
 use crate::shader::Shader;
 use crate::xmas_tree::mesh::{Mesh, Vertex};
 
 pub struct Ground {
     mesh: Mesh,
+    material_id: MaterialId,
+    transform: Matrix4<f32>,
 }
 
 impl Ground {
-    pub fn new(materials: &mut Materials) -> Self {
+    /// Ground is a square of size 2x2 on XZ plane, transformation gives it the final size and placement
+    pub fn new(material_id: MaterialId, transform: Matrix4<f32>) -> Self {
         let vertices: Vec<Vertex> = vec![
-            Vertex { position: Point3::new(-10., -5., -10.), normal: vec3(0., 1., 0.) },   // far
-            Vertex { position: Point3::new(-10., -5., 10.), normal: vec3(0., 1., 0.) }, // left
-            Vertex { position: Point3::new(10., -5., -10.), normal: vec3(0., 1., 0.) }, // right
-            Vertex { position: Point3::new(10., -5., 10.), normal: vec3(0., 1., 0.) }, // near
+            Vertex { position: Point3::new(-1., 0., -1.), normal: vec3(0., 1., 0.) },   // far
+            Vertex { position: Point3::new(-1., 0., 1.), normal: vec3(0., 1., 0.) }, // left
+            Vertex { position: Point3::new(1., 0., -1.), normal: vec3(0., 1., 0.) }, // right
+            Vertex { position: Point3::new(1., 0., 1.), normal: vec3(0., 1., 0.) }, // near
         ];
 
         let indices: Vec<u32> = vec![
@@ -23,25 +28,41 @@ impl Ground {
             1, 3, 2,
         ];
 
-        let ambient: Vector3<f32> = vec3(1., 1., 1.);
-        let diffuse: Vector3<f32> = vec3(0.623960, 0.686685, 0.693872);
-        let specular: Vector3<f32> = vec3(0.5, 0.5, 0.5);
-        let shininess: f32 = 225.;
-        let material = Material { ambient, diffuse, specular, shininess };
-        let material_id = materials.add(material);
-
         let mesh = Mesh::new(vertices, indices, 1);
-        mesh.fill_instances_vbo(&vec![Instance { model: Matrix4::identity(), material_id }]);
-        Self { mesh }
+        let ground = Self { mesh, material_id, transform };
+        ground.fill_instance(0.);
+        ground
+    }
+
+    fn fill_instance(&self, emission: f32) {
+        self.mesh.fill_instances_vbo(&[Instance { model: self.transform, material_id: self.material_id, emission }]);
     }
 }
 
 impl Model for Ground {
-    fn next_frame(&mut self) {
+    fn name(&self) -> &'static str {
+        "ground"
+    }
+
+    fn next_frame(&mut self, _dt: f32) {
         // nothing changes
     }
 
-    fn draw(&mut self, shader: &Shader) {
+    fn draw(&mut self, shader: &Shader) -> usize {
         self.mesh.draw_single(shader);
+        1
+    }
+
+    fn snow_surface(&self) -> Vec<[Point3<f32>; 3]> {
+        let corner = |x: f32, z: f32| self.transform.transform_point(Point3::new(x, 0., z));
+        vec![[corner(-1., -1.), corner(-1., 1.), corner(1., -1.)], [corner(-1., 1.), corner(1., 1.), corner(1., -1.)]]
+    }
+
+    fn pick(&self, ray: &Ray) -> Option<(usize, f32)> {
+        ray.in_local_space(&self.transform)?.intersect_horizontal_rect(0., 1., 1.).map(|t| (0, t))
+    }
+
+    fn highlight(&mut self, instance: Option<usize>) {
+        self.fill_instance(instance.map_or(0., |_| HIGHLIGHT_EMISSION));
     }
 }
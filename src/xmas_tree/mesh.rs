@@ -33,102 +33,179 @@ pub struct Mesh {
     indices: Vec<u32>,
     max_instances: usize,
     vao: VAO,
+    vbo: VBO,
+    ebo: EBO,
     instances_vbo: VBO,
+    /// VAOs drawing instances from buffers filled elsewhere, see add_instances_source()
+    instances_sources: Vec<VAO>,
+    /// index of the source used for drawing, the mesh's own instances when None
+    instances_source: Option<usize>,
 }
 
 impl Mesh {
     pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, max_instances: usize) -> Self {
         let instances_vbo = Self::create_instances_vbo(max_instances);
-        let vao = Self::create_vao(&vertices, &indices, instances_vbo);
-        let mesh = Self { indices, max_instances, vao, instances_vbo };
+        let (vao, vbo, ebo) = Self::create_vao(&vertices, &indices, instances_vbo);
+        let mesh = Self { indices, max_instances, vao, vbo, ebo, instances_vbo, instances_sources: vec![], instances_source: None };
         mesh
     }
 
-    fn create_vao(vertices: &Vec<Vertex>, indices: &Vec<u32>, instances_vbo: u32) -> VAO {
+    fn create_vao(vertices: &Vec<Vertex>, indices: &Vec<u32>, instances_vbo: u32) -> (VAO, VBO, EBO) {
         unsafe {
             let mut vao = 0 as VAO;
             gl::GenVertexArrays(1, &mut vao); // create VAO
             gl::BindVertexArray(vao); // ...and bind it
 
-            Self::create_vbo(vertices);
-            Self::create_ebo(indices);
-
-            let stride = Vertex::size() as GLsizei;
-            // tell GL how to interpret the data in VBO -> one triangle vertex takes 3 coordinates (x, y, z)
-            // this call also connects my VBO to this attribute
-            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
-            gl::EnableVertexAttribArray(0); // enable the attribute for position
-
-            // second three floats are for normal vector
-            gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<GLfloat>()) as *const c_void);
-            gl::EnableVertexAttribArray(1); // enable the attribute for colour
+            let vbo = Self::create_vbo(vertices);
+            let ebo = Self::create_ebo(indices);
+            Self::point_vertex_attributes(vbo);
 
             // enter instancing, using completely different VBO
-            gl::BindBuffer(gl::ARRAY_BUFFER, instances_vbo);
-            let vec4_size = mem::size_of::<Vector4<f32>>() as i32;
-            let instances_stride = Instance::size() as GLsizei;
-            // println!("Instances stride: {}, Instance.size: {}", instances_stride, Instance::size());
-
-            // model matrix with rotation and translation
-            // I need to do the calls below 4 times, because size can be at most 4, but I'm sending a matrix of size 16
-            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, instances_stride, ptr::null());
-            gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, instances_stride, vec4_size as *const c_void);
-            gl::VertexAttribPointer(4, 4, gl::FLOAT, gl::FALSE, instances_stride, (2 * vec4_size) as *const c_void);
-            gl::VertexAttribPointer(5, 4, gl::FLOAT, gl::FALSE, instances_stride, (3 * vec4_size) as *const c_void);
-            gl::EnableVertexAttribArray(2);
-            gl::EnableVertexAttribArray(3);
-            gl::EnableVertexAttribArray(4);
-            gl::EnableVertexAttribArray(5);
-            gl::VertexAttribDivisor(2, 1);    // every iteration
-            gl::VertexAttribDivisor(3, 1);    // every iteration
-            gl::VertexAttribDivisor(4, 1);    // every iteration
-            gl::VertexAttribDivisor(5, 1);    // every iteration
-
-            // material_id
-            gl::VertexAttribPointer(6, 1, gl::FLOAT, gl::FALSE, instances_stride, (4 * vec4_size) as *const c_void);
-            gl::EnableVertexAttribArray(6);
-            gl::VertexAttribDivisor(6, 1);    // every iteration
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0); // unbind instances VBO
+            Self::point_instance_attributes(instances_vbo, Instance::size(), 0);
+
             // do NOT unbind EBO, VAO would remember that
             gl::BindVertexArray(0); // unbind my VAO
-            vao
+            (vao, vbo, ebo)
         }
     }
 
-    fn create_vbo(vertices: &Vec<Vertex>) {
+    /// Points vertex attributes of the bound VAO to the vertex buffer
+    unsafe fn point_vertex_attributes(vbo: VBO) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let stride = Vertex::size() as GLsizei;
+        // tell GL how to interpret the data in VBO -> one triangle vertex takes 3 coordinates (x, y, z)
+        // this call also connects my VBO to this attribute
+        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
+        gl::EnableVertexAttribArray(0); // enable the attribute for position
+
+        // second three floats are for normal vector
+        gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<GLfloat>()) as *const c_void);
+        gl::EnableVertexAttribArray(1); // enable the attribute for colour
+    }
+
+    /// Points instance attributes of the bound VAO to the buffer, where every instance starts with the fields of Instance
+    /// and takes stride bytes, the first instance starts offset bytes into the buffer
+    unsafe fn point_instance_attributes(instances_vbo: VBO, stride: usize, offset: usize) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, instances_vbo);
+        let vec4_size = mem::size_of::<Vector4<f32>>();
+        let instances_stride = stride as GLsizei;
+
+        // model matrix with rotation and translation
+        // I need to do the calls below 4 times, because size can be at most 4, but I'm sending a matrix of size 16
+        gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, instances_stride, offset as *const c_void);
+        gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, instances_stride, (offset + vec4_size) as *const c_void);
+        gl::VertexAttribPointer(4, 4, gl::FLOAT, gl::FALSE, instances_stride, (offset + 2 * vec4_size) as *const c_void);
+        gl::VertexAttribPointer(5, 4, gl::FLOAT, gl::FALSE, instances_stride, (offset + 3 * vec4_size) as *const c_void);
+        gl::EnableVertexAttribArray(2);
+        gl::EnableVertexAttribArray(3);
+        gl::EnableVertexAttribArray(4);
+        gl::EnableVertexAttribArray(5);
+        gl::VertexAttribDivisor(2, 1);    // every iteration
+        gl::VertexAttribDivisor(3, 1);    // every iteration
+        gl::VertexAttribDivisor(4, 1);    // every iteration
+        gl::VertexAttribDivisor(5, 1);    // every iteration
+
+        // material_id
+        gl::VertexAttribPointer(6, 1, gl::FLOAT, gl::FALSE, instances_stride, (offset + 4 * vec4_size) as *const c_void);
+        gl::EnableVertexAttribArray(6);
+        gl::VertexAttribDivisor(6, 1);    // every iteration
+
+        // emission
+        gl::VertexAttribPointer(7, 1, gl::FLOAT, gl::FALSE, instances_stride, (offset + 4 * vec4_size + 4) as *const c_void);
+        gl::EnableVertexAttribArray(7);
+        gl::VertexAttribDivisor(7, 1);    // every iteration
+
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0); // unbind instances VBO
+    }
+
+    /// Sets up drawing instances from a buffer filled elsewhere, e.g. on the GPU, whose elements start with the fields of Instance
+    /// and take stride bytes, the first one starts offset bytes into the buffer. Returns index of the source for use_instances_source().
+    pub fn add_instances_source(&mut self, instances_vbo: VBO, stride: usize, offset: usize) -> usize {
+        unsafe {
+            let mut vao = 0 as VAO;
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+            Self::point_vertex_attributes(self.vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+            Self::point_instance_attributes(instances_vbo, stride, offset);
+            gl::BindVertexArray(0);
+            self.instances_sources.push(vao);
+        }
+        self.instances_sources.len() - 1
+    }
+
+    /// Makes the mesh draw instances from one of the added sources, it's only a switch between prepared VAOs
+    pub fn use_instances_source(&mut self, source: usize) {
+        self.instances_source = Some(source);
+    }
+
+    /// Removes all added sources, e.g. before their buffers get deleted, the mesh draws its own instances again
+    pub fn clear_instances_sources(&mut self) {
+        unsafe { gl::DeleteVertexArrays(self.instances_sources.len() as i32, self.instances_sources.as_ptr()) }
+        self.instances_sources.clear();
+        self.instances_source = None;
+    }
+
+    fn current_vao(&self) -> VAO {
+        self.instances_source.map_or(self.vao, |source| self.instances_sources[source])
+    }
+
+    fn create_vbo(vertices: &Vec<Vertex>) -> VBO {
         unsafe {
             let mut vbo = 0 as VBO;
             gl::GenBuffers(1, &mut vbo); // create buffer for my data
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo); // ARRAY_BUFFER now "points" to my buffer
             gl::BufferData(gl::ARRAY_BUFFER,
                            (vertices.len() * Vertex::size()) as GLsizeiptr,
-                           &vertices[0] as *const Vertex as *const c_void,
+                           vertices.as_ptr() as *const c_void,
                            gl::STATIC_DRAW); // actually fill ARRAY_BUFFER (my buffer) with data
+            vbo
         }
     }
 
-    fn create_ebo(indices: &[u32]) {
+    fn create_ebo(indices: &[u32]) -> EBO {
         unsafe {
             let mut ebo = 0 as EBO;
             gl::GenBuffers(1, &mut ebo); // create buffer for indices (elements)
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo); // ELEMENT_ARRAY_BUFFER now "points" to my buffer
             gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
                            (indices.len() * mem::size_of::<GLuint>()) as GLsizeiptr,
-                           &indices[0] as *const u32 as *const c_void,
+                           indices.as_ptr() as *const c_void,
                            gl::STATIC_DRAW); // actually fill ELEMENT_ARRAY_BUFFER with data
+            ebo
+        }
+    }
+
+    /// Replaces vertices and indices of the mesh, for meshes whose shape changes over time
+    pub fn replace_geometry(&mut self, vertices: &[Vertex], indices: Vec<u32>) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * Vertex::size()) as GLsizeiptr, vertices.as_ptr() as *const c_void, gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            // the element buffer binding is a part of VAO state
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<GLuint>()) as GLsizeiptr, indices.as_ptr() as *const c_void, gl::DYNAMIC_DRAW);
+            gl::BindVertexArray(0);
         }
+        self.indices = indices;
     }
 
-    pub fn fill_instances_vbo(&self, instances: &Vec<Instance>) {
+    /// Replaces all instances, can be called whenever they change. The buffer grows when there are more than max_instances of them.
+    pub fn fill_instances_vbo(&self, instances: &[Instance]) {
         // println!("Instance[0]: {:?}", instances[0]);
         // println!("Instance: {:?}", instances);
+        let capacity = instances.len().max(self.max_instances);
         unsafe {
             gl::BindBuffer(gl::ARRAY_BUFFER, self.instances_vbo); // ARRAY_BUFFER now "points" to my buffer
             gl::BufferData(gl::ARRAY_BUFFER,
-                           (self.max_instances * Instance::size()) as GLsizeiptr,
-                           instances.as_ptr() as *const c_void,
-                           gl::DYNAMIC_DRAW); // actually fill ARRAY_BUFFER (my buffer) with data
+                           (capacity * Instance::size()) as GLsizeiptr,
+                           ptr::null(),
+                           gl::DYNAMIC_DRAW); // orphan the old storage, so the GPU can still use it for frames in flight
+            gl::BufferSubData(gl::ARRAY_BUFFER,
+                              0,
+                              (instances.len() * Instance::size()) as GLsizeiptr,
+                              instances.as_ptr() as *const c_void); // actually fill ARRAY_BUFFER (my buffer) with data
         }
     }
 
@@ -145,10 +222,14 @@ impl Mesh {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
     pub fn draw_single(&self, shader: &Shader) {
         unsafe {
             gl::UseProgram(shader.id);
-            gl::BindVertexArray(self.vao);
+            gl::BindVertexArray(self.current_vao());
             gl::DrawElements(gl::TRIANGLES, self.indices.len() as i32, gl::UNSIGNED_INT, ptr::null());
             gl::BindVertexArray(0);
         }
@@ -157,7 +238,7 @@ impl Mesh {
     pub fn draw_instances(&mut self, shader: &Shader, num: usize) {
         unsafe {
             gl::UseProgram(shader.id);
-            gl::BindVertexArray(self.vao);
+            gl::BindVertexArray(self.current_vao());
             gl::DrawElementsInstanced(gl::TRIANGLES, self.indices.len() as i32, gl::UNSIGNED_INT, ptr::null(), num as i32);
             gl::BindVertexArray(0);
         }
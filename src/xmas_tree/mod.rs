@@ -1,6 +1,13 @@
 mod mesh;
 mod baubles;
+mod crystal;
+pub mod description;
+pub mod editor;
+mod fairy_lights;
 mod ground;
+#[cfg(test)]
+mod golden_tests;
 pub mod scene;
 mod snow;
+mod snow_cover;
 mod tree;
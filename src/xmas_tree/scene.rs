@@ -1,61 +1,319 @@
-use cgmath::{Point3, vec3};
+use std::collections::HashMap;
+
+use cgmath::{Deg, Point3, vec3, Vector3};
+use cgmath::prelude::*;
 use glfw::Window;
 
 use crate::camera::Camera;
-use crate::coords::SphericalPoint3;
-use crate::lights::Lights;
-use crate::material::Materials;
+use crate::collision::Collider;
+use crate::coords::{CylindricalPoint3, SphericalPoint3};
+use crate::lights::{Attenuation, Light, LightId, LightKind, Lights};
+use crate::material::{MaterialId, Materials};
 use crate::model::Model;
+use crate::picking::{Pick, Ray};
+use crate::profiler::{Profiler, Stage};
 use crate::shader::Shader;
-use crate::xmas_tree::baubles::Baubles;
+use crate::shadow::ShadowMap;
+use crate::wind::Wind;
+use crate::xmas_tree::baubles::{Bauble, Baubles};
+use crate::xmas_tree::description::{ModelDescription, SceneDescription};
+use crate::xmas_tree::fairy_lights::FairyLights;
 use crate::xmas_tree::ground::Ground;
 use crate::xmas_tree::snow::Snow;
 use crate::xmas_tree::tree::Tree;
 
+/// Spot light shining from the camera where it looks
+const HEADLAMP_INNER_ANGLE: Deg<f32> = Deg(12.);
+const HEADLAMP_OUTER_ANGLE: Deg<f32> = Deg(20.);
+const HEADLAMP_COLOR: [f32; 3] = [1., 0.95, 0.8];
+const HEADLAMP_RANGE: f32 = 30.;
+const HEADLAMP_ATTENUATION: Attenuation = Attenuation { constant: 1., linear: 0.05, quadratic: 0.01 };
+
 #[allow(dead_code)]
 pub struct Scene {
     pub camera: Camera,
     lights: Lights,
     shader: Shader,
     models: Vec<Box<dyn Model>>,
+    /// present only when one of the lights casts shadows
+    shadow_map: Option<ShadowMap>,
+    shadow_light: Option<LightId>,
+    shadows_enabled: bool,
+    /// lights from the description in their order
+    light_ids: Vec<LightId>,
+    headlamp: Option<LightId>,
+    /// model and instance under the cursor
+    hovered: Option<Pick>,
+    /// all materials from the description in their order
+    materials: Vec<(String, MaterialId)>,
+    /// made while drawing the last frame, including the shadow pass
+    draw_calls: usize,
+    pub profiler: Profiler,
+    pub wind: Wind,
 }
 
 impl Scene {
-    pub fn setup(window: &Window) -> Self {
-        let camera = Camera::new(SphericalPoint3::new(18., 1.7, 0.9), Point3::new(0., -1., 0.), &window);
+    /// Sets up the scene from its description, all randomness in it comes from given seed,
+    /// so the same seed gives the same animation
+    pub fn setup(window: &Window, description: &SceneDescription, seed: u64) -> Self {
+        let camera_description = &description.camera;
+        let position = SphericalPoint3::new(camera_description.r, camera_description.theta, camera_description.phi);
+        let camera = Camera::new(position, Point3::from(camera_description.look_at), camera_description.projection(), &window);
         let mut lights = Lights::setup();
-        lights.add(Point3::new(10., 100., 10.), vec3(0.3, 0.3, 0.3), vec3(0.2, 0.2, 0.2), vec3(0., 0., 0.));
-        lights.add(Point3::new(5., 6., 2.), vec3(0.2, 0.2, 0.2), vec3(2., 2., 2.), vec3(0.5, 0.5, 0.5));
+        let light_ids: Vec<LightId> = description.lights.iter().map(|l| lights.add(l.light())).collect();
         let mut materials = Materials::setup();
+        let named_materials: Vec<(String, MaterialId)> = description.materials.iter()
+            .map(|m| (m.name.clone(), materials.add(m.material())))
+            .collect();
+        let material_ids: HashMap<String, MaterialId> = named_materials.iter().cloned().collect();
 
-        let shader = Shader::new("src/xmas_tree/shaders/static.vert", "src/xmas_tree/shaders/static.frag");
+        let shader = Shader::new(&description.shaders.vertex, &description.shaders.fragment);
+        let shadow_light = description.lights.iter().position(|l| l.casts_shadow);
+        let shadow_map = shadow_light.map(|i| ShadowMap::new(&description.lights[i].light()));
 
-        let models = Scene::add_models(&mut materials);
-        Scene { camera, lights, shader, models }
+        let mut models = Scene::add_models(&mut materials, &material_ids, description, seed);
+        Scene::set_snow_surface(&mut models);
+        let mut scene = Scene { camera, lights, shader, models, shadow_map, shadow_light: shadow_light.map(|i| light_ids[i]), shadows_enabled: true, light_ids, headlamp: None, hovered: None, materials: named_materials, draw_calls: 0, profiler: Profiler::new(), wind: Wind::new(&description.wind) };
+        scene.update_dynamic_lights();
+        scene
     }
 
-    fn add_models(materials: &mut Materials) -> Vec<Box<dyn Model>> {
+    fn add_models(materials: &mut Materials, material_ids: &HashMap<String, MaterialId>, scene_description: &SceneDescription, seed: u64) -> Vec<Box<dyn Model>> {
+        let descriptions = &scene_description.models;
         let mut models: Vec<Box<dyn Model>> = Vec::new();
-        models.push(Box::new(Ground::new(materials)));
-        models.push(Box::new(Tree::new(materials)));
-        models.push(Box::new(Baubles::new(materials)));
-        models.push(Box::new(Snow::new(materials)));
+        for description in descriptions {
+            let model: Box<dyn Model> = match description {
+                ModelDescription::Ground { material, transform } => Box::new(Ground::new(material_ids[material], transform.matrix())),
+                ModelDescription::Tree { path, transform } => Box::new(Tree::new(materials, path, transform.matrix())),
+                ModelDescription::Baubles { radius, precision, baubles } => {
+                    let baubles = baubles.iter()
+                        .map(|b| Bauble { center: CylindricalPoint3::new(b.r, b.phi, b.h), material_id: material_ids[&b.material] })
+                        .collect();
+                    Box::new(Baubles::new(*radius, *precision, baubles))
+                }
+                ModelDescription::Snow { material, parameters } => Box::new(Snow::new(material_ids[material], parameters.clone(), seed)),
+                ModelDescription::FairyLights { materials: bulb_materials, parameters } => {
+                    let bulb_materials: Vec<(MaterialId, Vector3<f32>)> = bulb_materials.iter()
+                        .map(|name| {
+                            let material = scene_description.materials.iter().find(|m| &m.name == name).unwrap();
+                            (material_ids[name], Vector3::from(material.diffuse))
+                        })
+                        .collect();
+                    Box::new(FairyLights::new(&bulb_materials, parameters, parameters.pattern(seed)))
+                }
+            };
+            models.push(model);
+        }
         models
     }
 
-    pub fn next_frame(&mut self) {
-        for d in &mut self.models {
-            d.next_frame();
+    /// Makes snow land on whatever other models offer
+    fn set_snow_surface(models: &mut [Box<dyn Model>]) {
+        let triangles: Vec<[Point3<f32>; 3]> = models.iter().flat_map(|m| m.snow_surface()).collect();
+        for snow in models.iter_mut().filter_map(|m| m.as_any_mut().downcast_mut::<Snow>()) {
+            snow.set_surface(&triangles);
+        }
+    }
+
+    /// Makes snow bump into shapes of other models where they are now
+    fn update_snow_colliders(&mut self) {
+        let colliders: Vec<Collider> = self.models.iter().flat_map(|m| m.colliders()).collect();
+        for snow in self.models.iter_mut().filter_map(|m| m.as_any_mut().downcast_mut::<Snow>()) {
+            snow.set_colliders(&colliders);
+        }
+    }
+
+    /// Advances all models by dt seconds of simulation time
+    pub fn next_frame(&mut self, dt: f32) {
+        self.update_snow_colliders();
+        self.wind.advance(dt);
+        let profiler = &mut self.profiler;
+        let wind = &self.wind;
+        for (i, d) in self.models.iter_mut().enumerate() {
+            profiler.measure(i, d.name(), Stage::Update, || {
+                d.blow(wind);
+                d.next_frame(dt);
+            });
+        }
+        self.update_dynamic_lights();
+    }
+
+    fn update_dynamic_lights(&mut self) {
+        let dynamic_lights: Vec<Light> = self.models.iter().flat_map(|m| m.emitted_lights()).collect();
+        self.lights.set_dynamic(&dynamic_lights);
+    }
+
+    /// Finds the closest model instance hit by the ray, the tree and the ground hide whatever is behind them
+    pub fn pick(&self, ray: &Ray) -> Option<Pick> {
+        Pick::nearest(self.models.iter().map(|m| m.pick(ray)))
+    }
+
+    /// Highlights the instance hit by the ray
+    pub fn hover(&mut self, ray: &Ray) {
+        let picked = self.pick(ray);
+        let same = |a: Option<Pick>, b: Option<Pick>| a.map(|p| (p.model, p.instance)) == b.map(|p| (p.model, p.instance));
+        if same(picked, self.hovered) {
+            self.hovered = picked;
+            return;
+        }
+        if let Some(previous) = self.hovered {
+            self.models[previous.model].highlight(None);
+        }
+        if let Some(current) = picked {
+            self.models[current.model].highlight(Some(current.instance));
+        }
+        self.hovered = picked;
+    }
+
+    /// Removes the highlight, e.g. when the highlighted instance doesn't exist anymore
+    pub fn clear_hover(&mut self) {
+        if let Some(hovered) = self.hovered.take() {
+            self.models[hovered.model].highlight(None);
+        }
+    }
+
+    /// Index of the bauble under the cursor
+    pub fn hovered_bauble(&self) -> Option<usize> {
+        self.hovered
+            .filter(|p| self.models[p.model].as_any().is::<Baubles>())
+            .map(|p| p.instance)
+    }
+
+    /// First of the models holding baubles, that's the one being edited
+    pub fn baubles_mut(&mut self) -> Option<&mut Baubles> {
+        self.models.iter_mut().find_map(|m| m.as_any_mut().downcast_mut::<Baubles>())
+    }
+
+    /// Point where the ray hits the tree
+    pub fn pick_tree(&self, ray: &Ray) -> Option<Point3<f32>> {
+        self.models.iter()
+            .filter(|m| m.as_any().is::<Tree>())
+            .filter_map(|m| m.pick(ray))
+            .map(|(_, distance)| distance)
+            .fold(None, |closest: Option<f32>, distance| Some(closest.map_or(distance, |c| c.min(distance))))
+            .map(|distance| ray.at(distance))
+    }
+
+    /// Names and ids of all materials in the order they were described
+    pub fn materials(&self) -> &[(String, MaterialId)] {
+        &self.materials
+    }
+
+    /// Removes snow lying on the ground and the tree
+    pub fn reset_snow_cover(&mut self) {
+        for snow in self.models.iter_mut().filter_map(|m| m.as_any_mut().downcast_mut::<Snow>()) {
+            snow.reset_cover();
         }
     }
 
+    /// Multiplies the snowflakes landing per second in all snowfalls by the factor
+    pub fn change_snowfall(&mut self, factor: f32) {
+        self.set_snowfall_intensity(self.snowfall_intensity() * factor);
+    }
+
+    /// Makes given number of snowflakes land per second, split between the snowfalls like until now.
+    /// Snowfalls where snowflakes don't fall at all keep their number.
+    pub fn set_snowfall_intensity(&mut self, intensity: f32) {
+        let current = self.snowfall_intensity();
+        for snow in self.models.iter_mut().filter_map(|m| m.as_any_mut().downcast_mut::<Snow>()) {
+            if current > 0. {
+                snow.set_intensity(intensity * snow.intensity() / current);
+            }
+        }
+    }
+
+    /// Snowflakes landing per second in all snowfalls
+    pub fn snowfall_intensity(&self) -> f32 {
+        self.models.iter().filter_map(|m| m.as_any().downcast_ref::<Snow>()).map(|s| s.intensity()).sum()
+    }
+
+    /// Replaces a static light, the shadows follow the light casting them
+    fn update_light(&mut self, id: LightId, light: Light) {
+        self.lights.update(id, light);
+        if self.shadow_light == Some(id) {
+            if let Some(shadow_map) = &mut self.shadow_map {
+                shadow_map.set_light(&light);
+            }
+        }
+    }
+
+    /// Turns all lights from the description around the vertical axis
+    pub fn turn_lights(&mut self, angle: Deg<f32>) {
+        for id in self.light_ids.clone() {
+            if let Some(light) = self.lights.get(id) {
+                let turned = light.turned(angle.into());
+                self.update_light(id, turned);
+            }
+        }
+    }
+
+    /// Switches all lights from the description off when any of them is on, otherwise back on
+    pub fn toggle_lights(&mut self) {
+        let enabled = !self.light_ids.iter().any(|&id| self.lights.is_enabled(id));
+        for &id in &self.light_ids {
+            self.lights.set_enabled(id, enabled);
+        }
+    }
+
+    pub fn toggle_headlamp(&mut self) {
+        match self.headlamp.take() {
+            Some(id) => self.lights.remove(id),
+            None => self.headlamp = Some(self.lights.add(self.headlamp_light())),
+        }
+    }
+
+    fn headlamp_light(&self) -> Light {
+        let pose = self.camera.pose();
+        let kind = LightKind::Spot {
+            position: pose.eye,
+            direction: (pose.look_at - pose.eye).normalize(),
+            inner_angle: HEADLAMP_INNER_ANGLE,
+            outer_angle: HEADLAMP_OUTER_ANGLE,
+            attenuation: HEADLAMP_ATTENUATION,
+        };
+        let color = Vector3::from(HEADLAMP_COLOR);
+        Light { kind, ambient: vec3(0., 0., 0.), diffuse: color, specular: color, range: Some(HEADLAMP_RANGE) }
+    }
+
+    pub fn toggle_shadows(&mut self) {
+        self.shadows_enabled = !self.shadows_enabled;
+    }
+
     pub fn draw(&mut self) {
+        let mut draw_calls = 0;
         unsafe {
             gl::ClearColor(0.0157, 0., 0.3607, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-            for d in &mut self.models {
-                d.draw(&self.shader);
+        }
+        if let Some(id) = self.headlamp {
+            self.lights.update(id, self.headlamp_light());
+        }
+        let shadow_light = match &self.shadow_map {
+            Some(shadow_map) if self.shadows_enabled => {
+                let pass = shadow_map.begin();
+                let profiler = &mut self.profiler;
+                for (i, d) in self.models.iter_mut().enumerate() {
+                    draw_calls += profiler.measure(i, d.name(), Stage::Shadow, || d.draw(&shadow_map.shader));
+                }
+                shadow_map.end(pass, &self.shader);
+                self.shadow_light.and_then(|id| self.lights.buffer_index(id)).map_or(-1, |i| i as i32)
             }
+            _ => -1,
+        };
+        self.shader.set_int("shadowLight", shadow_light);
+        self.lights.cull(&self.camera.view(), &self.camera.projection(), &self.shader);
+        let (profiler, shader) = (&mut self.profiler, &self.shader);
+        for (i, d) in self.models.iter_mut().enumerate() {
+            draw_calls += profiler.measure(i, d.name(), Stage::Draw, || d.draw(shader));
         }
+        self.draw_calls = draw_calls;
+    }
+
+    pub fn draw_calls(&self) -> usize {
+        self.draw_calls
+    }
+
+    pub fn snowflakes(&self) -> usize {
+        self.models.iter().filter_map(|m| m.as_any().downcast_ref::<Snow>()).map(|s| s.count()).sum()
     }
 }
@@ -0,0 +1,12 @@
+#version 330 core
+
+in vec2 TexCoords;
+in vec4 Colour;
+
+uniform sampler2D atlas;
+
+out vec4 FragColor;
+
+void main() {
+    FragColor = vec4(Colour.rgb, Colour.a * texture(atlas, TexCoords).r);
+}
@@ -0,0 +1,19 @@
+#version 330 core
+
+layout (location = 0) in vec2 aPos;
+layout (location = 1) in vec2 aTexCoords;
+layout (location = 2) in vec4 aColour;
+
+// in pixels
+uniform vec2 screenSize;
+
+out vec2 TexCoords;
+out vec4 Colour;
+
+void main() {
+    // pixels go from the top left corner, normalized device coordinates from the bottom left one
+    vec2 ndc = aPos / screenSize * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    TexCoords = aTexCoords;
+    Colour = aColour;
+}
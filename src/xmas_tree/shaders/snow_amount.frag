@@ -0,0 +1,11 @@
+#version 330 core
+
+// Amount of snow added or melted, as a fraction of the maximal depth
+
+uniform float amount;
+
+out vec4 FragColor;
+
+void main() {
+    FragColor = vec4(amount);
+}
@@ -0,0 +1,15 @@
+#version 330 core
+
+// Draws snowflakes which landed in this frame as points into the snow depth texture
+
+layout (location = 0) in vec3 position;
+layout (location = 2) in float landed;
+
+// left, front, right and back edge of the snow depth texture
+uniform vec4 area;
+
+void main() {
+    vec2 uv = (position.xz - area.xy) / (area.zw - area.xy);
+    // snowflakes still in the air end up outside of the clip space
+    gl_Position = landed > 0.5 ? vec4(uv * 2.0 - 1.0, 0.0, 1.0) : vec4(2.0, 2.0, 2.0, 1.0);
+}
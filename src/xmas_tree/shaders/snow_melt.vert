@@ -0,0 +1,7 @@
+#version 330 core
+
+// Single triangle covering the whole snow depth texture
+
+void main() {
+    gl_Position = vec4(gl_VertexID == 1 ? 3.0 : -1.0, gl_VertexID == 2 ? 3.0 : -1.0, 0.0, 1.0);
+}
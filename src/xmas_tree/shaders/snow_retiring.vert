@@ -0,0 +1,16 @@
+#version 330 core
+
+// Together with snow_retiring.geom counts retiring snowflakes which haven't landed yet
+
+layout (location = 0) in vec3 position;
+
+uniform vec3 boxMin;
+
+out float falling;
+
+// the same as in snow_update.vert
+const float RETIRED_DEPTH = 1000.0;
+
+void main() {
+    falling = position.y < boxMin.y - 0.5 * RETIRED_DEPTH ? 0.0 : 1.0;
+}
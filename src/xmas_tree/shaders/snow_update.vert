@@ -0,0 +1,150 @@
+#version 330 core
+
+// Advances one snowflake per vertex, the outputs are captured with transform feedback into the other snow buffer
+
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec3 rotation;
+layout (location = 3) in float scale;
+
+uniform float dt;
+uniform int seed;
+// snowflakes blown out of the box on one side come back on the other one
+uniform vec3 boxMin;
+uniform vec3 boxMax;
+// height of the surface snowflakes land on over the area: left, front, right and back edge
+uniform sampler2D surface;
+uniform vec4 surfaceArea;
+uniform float fallVelocity;
+// largest random drift and spin per second, they make random walks of RANDOM_WALK_STEP long steps
+uniform float maxRandomOffset;
+// in radians
+uniform float maxRandomRotation;
+uniform float materialId;
+// snowflakes from this index on are retiring, they disappear when they land
+uniform int liveCount;
+// centers and radii including the size of a snowflake, snowflakes slide off them
+uniform samplerBuffer spheres;
+uniform int sphereCount;
+// vertical truncated cones, snowflakes land on them. Two texels each: bottom center and height, bottom and top radius
+uniform samplerBuffer frusta;
+uniform int frustumCount;
+// set by Wind::set_uniforms, every three waves make one component of the vector potential the turbulence is the curl of
+uniform vec3 wind;
+uniform float windTime;
+uniform float turbulence;
+// wave vector and angular frequency
+uniform vec4 windWaves[9];
+uniform float windPhases[9];
+
+out mat4 instanceModel;
+out float instanceMaterialId;
+out float instanceEmission;
+out vec3 nextPosition;
+out vec3 nextRotation;
+// 1 if the snowflake landed in this frame, 0 otherwise
+out float nextLanded;
+out float nextScale;
+
+// PCG hash
+uint hash(uint x) {
+    uint state = x * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Uniformly distributed in [-1, 1], different for every snowflake, frame and i
+float random(uint i) {
+    uint h = hash((uint(gl_VertexID) * 8u + i) ^ hash(uint(seed)));
+    return float(h) / 4294967295.0 * 2.0 - 1.0;
+}
+
+vec3 potentialGradient(int component, vec3 p) {
+    vec3 gradient = vec3(0.0);
+    for (int i = component * 3; i < component * 3 + 3; i++) {
+        vec3 k = windWaves[i].xyz;
+        gradient += k * cos(dot(k, p) + windWaves[i].w * windTime + windPhases[i]);
+    }
+    return gradient;
+}
+
+// the same as Wind::velocity_at
+vec3 windVelocity(vec3 p) {
+    vec3 x = potentialGradient(0, p);
+    vec3 y = potentialGradient(1, p);
+    vec3 z = potentialGradient(2, p);
+    return wind + turbulence * vec3(z.y - y.z, x.z - z.x, y.x - x.y);
+}
+
+bool insideFrustum(vec3 p, int i) {
+    vec4 frustum = texelFetch(frusta, 2 * i);
+    vec2 radii = texelFetch(frusta, 2 * i + 1).xy;
+    float t = (p.y - frustum.y) / frustum.w;
+    return t >= 0.0 && t <= 1.0 && length(p.xz - frustum.xz) < mix(radii.x, radii.y, t);
+}
+
+// a random walk spreads with the square root of time, its steps scaled like that add up the same at every frame rate
+const float RANDOM_WALK_STEP = 1.0 / 60.0;
+
+// retired snowflakes wait that far below the box until they are live again
+const float RETIRED_DEPTH = 1000.0;
+
+bool isRetired(vec3 p) {
+    return p.y < boxMin.y - 0.5 * RETIRED_DEPTH;
+}
+
+void main() {
+    bool live = gl_VertexID < liveCount;
+    nextScale = scale;
+    instanceMaterialId = materialId;
+    instanceEmission = 0.0;
+    if (isRetired(position) && !live) {
+        nextPosition = position;
+        nextRotation = rotation;
+        nextLanded = 0.0;
+        instanceModel = mat4(0.0);
+        return;
+    }
+    // snowflakes live again start at the top
+    vec3 start = isRetired(position) ? vec3(position.x, boxMax.y, position.z) : position;
+    float walk = sqrt(dt / RANDOM_WALK_STEP);
+    vec3 offset = maxRandomOffset * RANDOM_WALK_STEP * walk * vec3(random(0u), random(1u), random(2u));
+    nextPosition = start + offset + (windVelocity(start) - vec3(0.0, fallVelocity, 0.0)) * dt;
+    nextPosition.xz = boxMin.xz + mod(nextPosition.xz - boxMin.xz, boxMax.xz - boxMin.xz);
+    bool onSphere = false;
+    for (int i = 0; i < sphereCount; i++) {
+        vec4 sphere = texelFetch(spheres, i);
+        vec3 away = nextPosition - sphere.xyz;
+        float distance = length(away);
+        if (distance < sphere.w && distance > 0.0) {
+            // pushed back to the surface, so the snowflake keeps moving along it
+            nextPosition = sphere.xyz + away / distance * sphere.w;
+            onSphere = true;
+        }
+    }
+    // spheres stick out of the frusta or lie inside them, snowflakes sliding off a sphere are not caught until they leave it
+    bool caught = false;
+    for (int i = 0; i < frustumCount && !onSphere; i++) {
+        caught = caught || insideFrustum(nextPosition, i);
+    }
+    vec2 uv = (nextPosition.xz - surfaceArea.xy) / (surfaceArea.zw - surfaceArea.xy);
+    nextLanded = 0.0;
+    if (caught || nextPosition.y < texture(surface, uv).r) {
+        // it appears again at the top, right above the place it landed in, unless it's retiring
+        nextPosition.y = live ? boxMax.y : boxMin.y - RETIRED_DEPTH;
+        nextLanded = 1.0;
+    }
+    nextRotation = rotation + maxRandomRotation * RANDOM_WALK_STEP * walk * vec3(random(3u), random(4u), random(5u));
+    float size = isRetired(nextPosition) ? 0.0 : scale;
+
+    // the same rotation as cgmath makes from Euler angles, preceded by the scale and followed by the translation
+    vec3 s = sin(nextRotation);
+    vec3 c = cos(nextRotation);
+    instanceModel = mat4(
+        c.y * c.z, c.x * s.z + s.x * s.y * c.z, s.x * s.z - c.x * s.y * c.z, 0.0,
+        -c.y * s.z, c.x * c.z - s.x * s.y * s.z, s.x * c.z + c.x * s.y * s.z, 0.0,
+        s.y, -s.x * c.y, c.x * c.y, 0.0,
+        nextPosition, 1.0);
+    instanceModel[0].xyz *= size;
+    instanceModel[1].xyz *= size;
+    instanceModel[2].xyz *= size;
+}
@@ -6,17 +6,32 @@ struct Material {
     vec4 specular;
 };
 
+const int DIRECTIONAL = 0;
+const int POINT = 1;
+const int SPOT = 2;
+
 struct Light {
+    int kind;
     vec3 position;
+    vec3 direction;
+    // 0 when the light reaches everywhere
+    float range;
 
     vec3 ambient;
     vec3 diffuse;
     vec3 specular;
+
+    // cosines of the spot light's cone angles
+    float innerCutoff;
+    float outerCutoff;
+    // constant, linear and quadratic factor
+    vec3 attenuation;
 };
 
 in vec3 FragPosition;
 in vec3 Normal;
 flat in uint MaterialId;
+flat in float Emission;
 
 layout (std140) uniform Camera {
     vec3 cameraPosition;
@@ -24,32 +39,82 @@ layout (std140) uniform Camera {
     mat4 projection;
 };
 
-layout (std140) uniform Lights {
-    int lightsNo;
-    Light light[4];
-};
+// every light takes 6 texels: position with kind, direction with range, ambient with inner cutoff,
+// diffuse with outer cutoff, specular and attenuation
+uniform samplerBuffer lights;
+// screen is divided into tiles of tileSize pixels, for every tile there's an offset into lightIndices and number of lights reaching it
+uniform usamplerBuffer lightTiles;
+uniform usamplerBuffer lightIndices;
+uniform int tileSize;
+uniform int tilesX;
 
 layout (std140) uniform Materials {
     Material material[100];
 };
 
+uniform sampler2D shadowMap;
+uniform mat4 lightSpace;
+// index of the light casting shadows, -1 when shadows are disabled
+uniform int shadowLight;
+
 out vec4 FragColor;
 
-vec3 calcLight(Light light);
+Light fetchLight(int index);
+vec3 calcLight(Light light, bool castsShadow);
+float calcShadow(vec3 norm, vec3 lightDir);
 
 void main() {
     vec3 result = vec3(0.0);
-    for (int i = 0; i < lightsNo; i++) {
-        result += calcLight(light[i]);
+    ivec2 tile = ivec2(gl_FragCoord.xy) / tileSize;
+    uvec2 tileLights = texelFetch(lightTiles, tile.y * tilesX + tile.x).xy;
+    for (uint i = 0u; i < tileLights.y; i++) {
+        int index = int(texelFetch(lightIndices, int(tileLights.x + i)).r);
+        result += calcLight(fetchLight(index), index == shadowLight);
     }
+    result += Emission * material[MaterialId].diffuse;
     FragColor = vec4(result, 1.0);
 }
 
-vec3 calcLight(Light light) {
+Light fetchLight(int index) {
+    int first = 6 * index;
+    vec4 position = texelFetch(lights, first);
+    vec4 direction = texelFetch(lights, first + 1);
+    vec4 ambient = texelFetch(lights, first + 2);
+    vec4 diffuse = texelFetch(lights, first + 3);
+    return Light(
+        int(position.w), position.xyz, direction.xyz, direction.w,
+        ambient.rgb, diffuse.rgb, texelFetch(lights, first + 4).rgb,
+        ambient.w, diffuse.w, texelFetch(lights, first + 5).xyz
+    );
+}
+
+vec3 calcLight(Light light, bool castsShadow) {
+    float attenuation = 1.0;
+    vec3 lightDir;
+    if (light.kind == DIRECTIONAL) {
+        lightDir = -light.direction;
+    } else {
+        float distance = length(light.position - FragPosition);
+        lightDir = (light.position - FragPosition) / distance;
+        attenuation = 1.0 / (light.attenuation.x + light.attenuation.y * distance + light.attenuation.z * distance * distance);
+        if (light.range > 0.0) {
+            // smoothly falls to 0 at the range
+            float ratio = distance / light.range;
+            attenuation *= pow(clamp(1.0 - ratio * ratio, 0.0, 1.0), 2.0);
+        }
+        if (light.kind == SPOT) {
+            float cosAngle = dot(-lightDir, light.direction);
+            attenuation *= clamp((cosAngle - light.outerCutoff) / max(light.innerCutoff - light.outerCutoff, 0.0001), 0.0, 1.0);
+        }
+        if (attenuation == 0.0) {
+            return vec3(0.0);
+        }
+    }
+
     vec3 ambient = light.ambient * material[MaterialId].ambient;
 
-    vec3 norm = normalize(Normal);
-    vec3 lightDir = normalize(light.position - FragPosition);
+    // back faces are only drawn for flat models like snowflakes, they are lit from the other side
+    vec3 norm = normalize(gl_FrontFacing ? Normal : -Normal);
     float diff = max(dot(norm, lightDir), 0.0);
     vec3 diffuse = diff * light.diffuse * material[MaterialId].diffuse;
 
@@ -58,5 +123,29 @@ vec3 calcLight(Light light) {
     float spec = pow(max(dot(norm, halfwayDir), 0.0), material[MaterialId].specular.w);
     vec3 specular = spec * light.specular * vec3(material[MaterialId].specular);
 
-    return ambient + diffuse + specular;
+    float shadow = castsShadow ? calcShadow(norm, lightDir) : 0.0;
+    return attenuation * (ambient + (1.0 - shadow) * (diffuse + specular));
+}
+
+// returns how much the fragment is in the shadow, 0 - fully lit, 1 - fully in shadow
+float calcShadow(vec3 norm, vec3 lightDir) {
+    vec4 lightSpacePosition = lightSpace * vec4(FragPosition, 1.0);
+    if (lightSpacePosition.w <= 0.0) {
+        return 0.0; // behind the light
+    }
+    vec3 projected = lightSpacePosition.xyz / lightSpacePosition.w * 0.5 + 0.5;
+    if (projected.z > 1.0) {
+        return 0.0; // further than the shadow map reaches
+    }
+    float bias = max(0.0005 * (1.0 - dot(norm, lightDir)), 0.0001);
+    // percentage-closer filtering, averaging 3x3 neighbouring texels gives soft edges
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0));
+    float shadow = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            float closestDepth = texture(shadowMap, projected.xy + vec2(x, y) * texelSize).r;
+            shadow += projected.z - bias > closestDepth ? 1.0 : 0.0;
+        }
+    }
+    return shadow / 9.0;
 }
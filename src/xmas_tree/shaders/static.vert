@@ -4,6 +4,7 @@ layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aNormal;
 layout (location = 2) in mat4 instanceModel;
 layout (location = 6) in float instanceMaterialId;
+layout (location = 7) in float instanceEmission;
 
 layout (std140) uniform Camera {
     vec3 cameraPosition;
@@ -14,6 +15,7 @@ layout (std140) uniform Camera {
 out vec3 FragPosition;
 out vec3 Normal;
 flat out uint MaterialId;
+flat out float Emission;
 
 void main() {
     vec4 pos = instanceModel * vec4(aPos, 1.0);
@@ -21,4 +23,5 @@ void main() {
     FragPosition = vec3(pos);
     Normal = mat3(transpose(inverse(instanceModel))) * aNormal;
     MaterialId = uint(instanceMaterialId);
+    Emission = instanceEmission;
 }
@@ -2,93 +2,328 @@ extern crate gl;
 extern crate rand;
 
 use core::f32::consts::PI;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
 
 use cgmath::{Euler, Matrix4, Point3, Rad, vec3, Vector3};
 use rand::{Rng, SeedableRng};
 use rand::distributions::Uniform;
 use rand::rngs::SmallRng;
+use serde::Deserialize;
 
-use crate::material::{Material, MaterialId, Materials};
+use crate::collision::Collider;
+use crate::material::MaterialId;
 use crate::model::{Instance, Model};
 use crate::shader::Shader;
-use crate::xmas_tree::mesh::{Mesh, Vertex};
+use crate::texture_buffer::TextureBuffer;
+use crate::wind::Wind;
+use crate::xmas_tree::crystal::gen_crystal;
+use crate::xmas_tree::mesh::Mesh;
+use crate::xmas_tree::snow_cover::{SnowCover, SnowCoverParameters, SURFACE_RESOLUTION, SurfaceMap};
 
-const SNOW_X_MIN: f32 = -10.;
-const SNOW_X_MAX: f32 = 10.;
-const SNOW_Y_MIN: f32 = -5.;
-const SNOW_Y_MAX: f32 = 10.;
-const SNOW_Z_MIN: f32 = -10.;
-const SNOW_Z_MAX: f32 = 10.;
+/// Snowfall settings, velocities are per second, rotation is in degrees per second.
+/// Random offset and rotation make a random walk, they add up the same at every frame rate,
+/// on average like at 60 frames per second with steps of up to 1/60 of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnowParameters {
+    pub count: usize,
+    /// snowflakes fall inside a box between min and max corners, the ones landing on the ground, the tree
+    /// or the bottom of the box appear again at the top, the ones blown out of a side appear at the opposite one
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub fall_velocity: f32,
+    pub max_random_offset: f32,
+    pub max_random_rotation: f32,
+    /// without it landed snowflakes disappear without a trace
+    #[serde(default)]
+    pub cover: Option<SnowCoverParameters>,
+    /// number of different crystal shapes, snowflakes are split evenly between them
+    #[serde(default = "default_variants")]
+    pub variants: usize,
+    /// every snowflake gets a random size between these multiples of the basic one
+    #[serde(default = "default_scale")]
+    pub min_scale: f32,
+    #[serde(default = "default_scale")]
+    pub max_scale: f32,
+}
+
+fn default_variants() -> usize {
+    6
+}
+
+fn default_scale() -> f32 {
+    1.
+}
 
-const SNOWFLAKE_FALL_VELOCITY: f32 = 0.01;
-const SNOWFLAKE_MAX_RANDOM_OFFSET: f32 = 0.01;
-const SNOWFLAKE_MAX_RANDOM_ROTATION: f32 = PI / 180. * 10.;
-const MAX_SNOWFLAKES: usize = 5_000;
+impl SnowParameters {
+    /// Snowflakes landing per second out of given number of them, when there's no wind
+    pub fn intensity(&self, count: usize) -> f32 {
+        count as f32 * self.fall_velocity / (self.max[1] - self.min[1])
+    }
+
+    /// Number of snowflakes landing given number of them per second, None when they don't fall at all
+    pub fn count_for_intensity(&self, intensity: f32) -> Option<usize> {
+        if self.fall_velocity <= 0. {
+            return None;
+        }
+        Some((intensity.max(0.) * (self.max[1] - self.min[1]) / self.fall_velocity).round() as usize)
+    }
+
+    /// Returns name of the invalid field together with the reason
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if self.count == 0 {
+            return Err(("count", "there has to be at least one snowflake".to_owned()));
+        }
+        if self.count > MAX_SNOWFLAKES {
+            return Err(("count", format!("there can be at most {} snowflakes", MAX_SNOWFLAKES)));
+        }
+        if (0..3).any(|i| self.min[i] >= self.max[i]) {
+            return Err(("max", "has to be greater than min on every axis".to_owned()));
+        }
+        if self.fall_velocity < 0. {
+            return Err(("fall_velocity", "cannot be negative".to_owned()));
+        }
+        if self.max_random_offset < 0. {
+            return Err(("max_random_offset", "cannot be negative".to_owned()));
+        }
+        if self.max_random_rotation < 0. {
+            return Err(("max_random_rotation", "cannot be negative".to_owned()));
+        }
+        if self.variants == 0 || self.variants > MAX_VARIANTS {
+            return Err(("variants", format!("there has to be between 1 and {} crystal shapes", MAX_VARIANTS)));
+        }
+        if !(self.min_scale > 0. && self.min_scale <= self.max_scale) {
+            return Err(("max_scale", "scales have to satisfy 0 < min_scale <= max_scale".to_owned()));
+        }
+        if let Some(cover) = &self.cover {
+            if cover.flake_depth <= 0. || cover.max_depth <= 0. || cover.melt_rate < 0. {
+                return Err(("cover", "depths have to be positive and melt rate cannot be negative".to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
 
 struct Snowflake {
     position: Vector3<f32>,
     rotation: Vector3<Rad<f32>>,
+    scale: f32,
+}
+
+/// Element of the snow buffers: the instance drawn for a snowflake followed by the state it's made from
+#[repr(C)]
+struct Particle {
+    instance: Instance,
+    position: Vector3<f32>,
+    rotation: Vector3<f32>,
+    /// 1 in the frame the snowflake landed, 0 otherwise
+    landed: f32,
+    scale: f32,
+}
+
+impl Particle {
+    fn new(snowflake: &Snowflake, material_id: MaterialId) -> Self {
+        let rotation = Matrix4::from(Euler { x: snowflake.rotation.x, y: snowflake.rotation.y, z: snowflake.rotation.z });
+        let translation = Matrix4::from_translation(snowflake.position);
+        let model = translation * rotation * Matrix4::from_scale(snowflake.scale);
+        Particle {
+            instance: Instance { model, material_id, emission: 0. },
+            position: snowflake.position,
+            rotation: vec3(snowflake.rotation.x.0, snowflake.rotation.y.0, snowflake.rotation.z.0),
+            landed: 0.,
+            scale: snowflake.scale,
+        }
+    }
 }
 
+/// Outputs of the update shader in the order of Particle fields, together with their sizes in bytes
+const FEEDBACK_VARYINGS: [(&str, usize); 7] = [
+    ("instanceModel", 64), ("instanceMaterialId", 4), ("instanceEmission", 4), ("nextPosition", 12), ("nextRotation", 12), ("nextLanded", 4), ("nextScale", 4),
+];
+/// Units 0-4 are taken by the shadow map, the light buffers and the overlay atlas
+const SURFACE_TEXTURE_UNIT: u32 = 5;
+const SPHERES_TEXTURE_UNIT: u32 = 6;
+const FRUSTA_TEXTURE_UNIT: u32 = 7;
+/// Number of colliders the collider buffers have room for at the start, they grow when needed
+const INITIAL_COLLIDERS_CAPACITY: isize = 64;
+/// Radius of a snowflake with scale 1
+const SNOWFLAKE_RADIUS: f32 = 0.05;
+/// Meshes draw every n-th snowflake for n variants, this keeps their stride within what all drivers support
+const MAX_VARIANTS: usize = 16;
+/// Upper limit of set_count(), every snow buffer takes about 100 MB then
+const MAX_SNOWFLAKES: usize = 1_000_000;
+
+/// Numbers of snowflakes in the snow buffers
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SnowCounts {
+    /// snowflakes which keep falling, only these are drawn
+    count: usize,
+    /// snowflakes simulated in the buffers, the ones past count are retiring
+    simulated: usize,
+    /// number of snowflakes the buffers have room for
+    capacity: usize,
+}
+
+impl SnowCounts {
+    fn new(count: usize) -> Self {
+        SnowCounts { count, simulated: count, capacity: count }
+    }
+
+    /// Counts after changing the number of falling snowflakes, it's clamped to 1..=MAX_SNOWFLAKES.
+    /// Retiring snowflakes below the new count keep falling, more than all simulated ones are added at the end.
+    /// Buffers at least double when they grow, so that they don't grow too often.
+    fn with_count(self, count: usize) -> Self {
+        let count = count.clamp(1, MAX_SNOWFLAKES);
+        let simulated = self.simulated.max(count);
+        let capacity = if simulated > self.capacity { simulated.max((2 * self.capacity).min(MAX_SNOWFLAKES)) } else { self.capacity };
+        SnowCounts { count, simulated, capacity }
+    }
+
+    /// Counts after all retiring snowflakes landed
+    fn retired(self) -> Self {
+        SnowCounts { simulated: self.count, ..self }
+    }
+}
+
+/// Snowflakes are simulated on the GPU: every frame the update shader reads them from one buffer
+/// and writes them with transform feedback into the other one, which is then drawn.
+/// Snowflakes land on the surface given by set_surface(), the bottom of the box until then.
+/// Every crystal shape has its own mesh, with n of them the i-th one draws every n-th snowflake starting with the i-th one.
+/// Their number changes at runtime: new ones start falling above the box, the removed ones aren't drawn anymore,
+/// but they're simulated until a query finds none of them falling, so that the snow they carry still lands on the cover.
 pub struct Snow {
-    mesh: Mesh,
-    snowflakes: Vec<Snowflake>,
+    meshes: Vec<Mesh>,
+    update_shader: Shader,
+    buffers: [u32; 2],
+    /// read snowflakes from the respective buffers
+    vaos: [u32; 2],
+    /// index of the buffer with the current snowflakes
+    current: usize,
+    counts: SnowCounts,
+    /// counts retiring snowflakes still falling, see check_retiring()
+    retiring_shader: Shader,
+    retiring_query: u32,
+    /// the query was started and its result isn't available yet
+    retiring_query_pending: bool,
     material_id: MaterialId,
+    parameters: SnowParameters,
+    /// height of the surface as a single channel float texture
+    surface_texture: u32,
+    /// the ones in the collider buffers
+    colliders: Vec<Collider>,
+    spheres_buffer: TextureBuffer,
+    frusta_buffer: TextureBuffer,
+    cover: Option<SnowCover>,
+    rng: SmallRng,
 }
 
 impl Snow {
-    pub fn new(materials: &mut Materials) -> Self {
-        let ambient: Vector3<f32> = vec3(1., 1., 1.);
-        let diffuse: Vector3<f32> = vec3(0.623960, 0.686685, 0.693872);
-        let specular: Vector3<f32> = vec3(0.5, 0.5, 0.5);
-        let shininess: f32 = 225.;
-        let material = Material { ambient, diffuse, specular, shininess };
-        let material_id = materials.add(material);
-
-        let (vertices, indices) = Snow::gen_snowflake_mesh();
-        let mesh = Mesh::new(vertices, indices, MAX_SNOWFLAKES);
-
-        let snowflakes = Snow::gen_snowflakes();
-        let snow = Self { mesh, snowflakes, material_id };
-        let instances = snow.gen_instances();
-        snow.mesh.fill_instances_vbo(&instances);
+    pub fn new(material_id: MaterialId, parameters: SnowParameters, seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let count = parameters.count;
+        let particles: Vec<Particle> = Snow::gen_snowflakes(&parameters, count, (parameters.min[1], parameters.max[1]), &mut rng).iter()
+            .map(|snowflake| Particle::new(snowflake, material_id))
+            .collect();
+        let (buffers, vaos) = Snow::create_buffers(count);
+        // instances are drawn straight from the snow buffers, the meshes don't need their own ones
+        let mut meshes: Vec<Mesh> = (0..parameters.variants)
+            .map(|_| {
+                let (vertices, indices) = gen_crystal(SNOWFLAKE_RADIUS, &mut rng);
+                Mesh::new(vertices, indices, 0)
+            })
+            .collect();
+        Snow::add_instances_sources(&mut meshes, &buffers);
+
+        let varyings: Vec<&str> = FEEDBACK_VARYINGS.iter().map(|(name, _)| *name).collect();
+        let update_shader = Shader::with_feedback("src/xmas_tree/shaders/snow_update.vert", &varyings);
+        update_shader.set_vec3("boxMin", &parameters.min);
+        update_shader.set_vec3("boxMax", &parameters.max);
+        update_shader.set_float("fallVelocity", parameters.fall_velocity);
+        update_shader.set_float("maxRandomOffset", parameters.max_random_offset);
+        update_shader.set_float("maxRandomRotation", parameters.max_random_rotation.to_radians());
+        update_shader.set_float("materialId", material_id);
+        update_shader.set_int("surface", SURFACE_TEXTURE_UNIT as i32);
+        update_shader.set_int("spheres", SPHERES_TEXTURE_UNIT as i32);
+        update_shader.set_int("frusta", FRUSTA_TEXTURE_UNIT as i32);
+        update_shader.set_int("sphereCount", 0);
+        update_shader.set_int("frustumCount", 0);
+        update_shader.set_int("liveCount", count as i32);
+        Wind::set_wave_uniforms(&update_shader);
+        let retiring_shader = Shader::with_geometry("src/xmas_tree/shaders/snow_retiring.vert", "src/xmas_tree/shaders/snow_retiring.geom");
+        retiring_shader.set_vec3("boxMin", &parameters.min);
+        let (mut surface_texture, mut retiring_query) = (0_u32, 0_u32);
+        unsafe {
+            gl::GenTextures(1, &mut surface_texture);
+            gl::GenQueries(1, &mut retiring_query);
+        }
+        let mut snow = Self {
+            meshes, update_shader, buffers, vaos, current: 0, counts: SnowCounts::new(count),
+            retiring_shader, retiring_query, retiring_query_pending: false,
+            material_id, parameters, surface_texture, cover: None, rng, colliders: vec![],
+            spheres_buffer: TextureBuffer::new(gl::RGBA32F, INITIAL_COLLIDERS_CAPACITY * mem::size_of::<[f32; 4]>() as isize),
+            frusta_buffer: TextureBuffer::new(gl::RGBA32F, INITIAL_COLLIDERS_CAPACITY * mem::size_of::<[[f32; 4]; 2]>() as isize),
+        };
+        snow.write_particles(0, &particles);
+        snow.use_current_buffer();
+        snow.set_surface(&[]);
         snow
     }
 
-    fn gen_snowflake_mesh() -> (Vec<Vertex>, Vec<u32>) {
-        let radius: f32 = 0.05;
-        let normal: Vector3<f32> = vec3(1., 0., 0.);
-        let mut vertices: Vec<Vertex> = vec![];
-
-        let angle_diff = PI / 3 as f32;
+    /// Makes snowflakes land on the upward facing triangles or the bottom of the box, whichever is higher.
+    /// Landed snowflakes pile up there if the snow cover is enabled, any snow lying before is removed.
+    pub fn set_surface(&mut self, triangles: &[[Point3<f32>; 3]]) {
+        let (min, max) = (self.parameters.min, self.parameters.max);
+        let mut surface = SurfaceMap::new([min[0], min[2]], [max[0], max[2]], SURFACE_RESOLUTION, min[1]);
+        for triangle in triangles {
+            surface.add_triangle(triangle);
+        }
+        let resolution = surface.resolution as i32;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.surface_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R32F as i32, resolution, resolution, 0, gl::RED, gl::FLOAT,
+                           surface.heights.as_ptr() as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        self.update_shader.set_vec4("surfaceArea", &surface.area());
+        self.cover = self.parameters.cover.clone().map(|parameters| SnowCover::new(parameters, surface, self.material_id));
+    }
 
-        for i in 0..6 {
-            let angle = i as f32 * angle_diff;
-            // upper side
-            vertices.push(Vertex { position: Point3::new(0., radius * angle.cos(), radius * angle.sin()), normal });
-            // bottom side
-            vertices.push(Vertex { position: Point3::new(-0., -radius * angle.cos(), -radius * angle.sin()), normal: -normal });
+    /// Snowflakes slide off the spheres and land on the frusta, the buffers are uploaded only when the colliders change
+    pub fn set_colliders(&mut self, colliders: &[Collider]) {
+        if colliders == self.colliders.as_slice() {
+            return;
         }
-        let indices: Vec<u32> = vec![
-            // upper side
-            8, 4, 0,
-            10, 6, 2,
-            // bottom side
-            1, 5, 9,
-            3, 7, 11,
-        ];
+        // snowflakes are kept outside of spheres by their own size too
+        let (spheres, frusta) = collider_texels(colliders, SNOWFLAKE_RADIUS * self.parameters.max_scale);
+        self.spheres_buffer.upload(&spheres);
+        self.frusta_buffer.upload(&frusta);
+        self.update_shader.set_int("sphereCount", spheres.len() as i32);
+        self.update_shader.set_int("frustumCount", frusta.len() as i32);
+        self.colliders = colliders.to_vec();
+    }
 
-        (vertices, indices)
+    /// Removes all snow lying on the surface
+    pub fn reset_cover(&mut self) {
+        if let Some(cover) = &mut self.cover {
+            cover.reset();
+        }
     }
 
-    fn gen_snowflakes() -> Vec<Snowflake> {
-        let mut snowflakes: Vec<Snowflake> = Vec::with_capacity(MAX_SNOWFLAKES as usize);
-        let x_range = Uniform::new(SNOW_X_MIN, SNOW_X_MAX);
-        let y_range = Uniform::new(SNOW_Y_MIN, SNOW_Y_MAX);
-        let z_range = Uniform::new(SNOW_Z_MIN, SNOW_Z_MAX);
+    /// Snowflakes spread over the box horizontally and between given heights vertically
+    fn gen_snowflakes(parameters: &SnowParameters, count: usize, heights: (f32, f32), rng: &mut SmallRng) -> Vec<Snowflake> {
+        let mut snowflakes: Vec<Snowflake> = Vec::with_capacity(count);
+        let x_range = Uniform::new(parameters.min[0], parameters.max[0]);
+        let y_range = Uniform::new(heights.0, heights.1);
+        let z_range = Uniform::new(parameters.min[2], parameters.max[2]);
         let angle_range = Uniform::new(0., 2. * PI);
-        let mut rng = SmallRng::from_entropy();
-        for _i in 0..MAX_SNOWFLAKES {
+        let scale_range = Uniform::new_inclusive(parameters.min_scale, parameters.max_scale);
+        for _i in 0..count {
             let x_position = rng.sample(x_range);
             let y_position = rng.sample(y_range);
             let z_position = rng.sample(z_range);
@@ -97,53 +332,323 @@ impl Snow {
             let z_rotation = Rad(rng.sample(angle_range));
             let position = vec3(x_position, y_position, z_position);
             let rotation = vec3(x_rotation, y_rotation, z_rotation);
-            snowflakes.push(Snowflake { position, rotation });
+            snowflakes.push(Snowflake { position, rotation, scale: rng.sample(scale_range) });
         }
         snowflakes
     }
 
-    fn move_snowflakes(&mut self) {
-        let mut rng = SmallRng::from_entropy();
-        let pos_offset_range = Uniform::new(-SNOWFLAKE_MAX_RANDOM_OFFSET as f32, SNOWFLAKE_MAX_RANDOM_OFFSET);
-        let rot_angle_range = Uniform::new(-SNOWFLAKE_MAX_RANDOM_ROTATION, SNOWFLAKE_MAX_RANDOM_ROTATION);
-        for i in 0..MAX_SNOWFLAKES as usize {
-            let mut snowflake = &mut self.snowflakes[i];
-            let new_x_pos = snowflake.position.x + rng.sample(pos_offset_range);
-            let mut new_y_pos = snowflake.position.y + rng.sample(pos_offset_range) - SNOWFLAKE_FALL_VELOCITY;
-            if new_y_pos < SNOW_Y_MIN {
-                new_y_pos = SNOW_Y_MAX;
+    /// Empty buffers with room for given number of snowflakes, each of them gets a VAO the snowflakes are read with
+    fn create_buffers(capacity: usize) -> ([u32; 2], [u32; 2]) {
+        let (mut buffers, mut vaos) = ([0_u32; 2], [0_u32; 2]);
+        let stride = mem::size_of::<Particle>() as i32;
+        unsafe {
+            gl::GenBuffers(2, buffers.as_mut_ptr());
+            gl::GenVertexArrays(2, vaos.as_mut_ptr());
+            for (buffer, vao) in buffers.iter().zip(vaos.iter()) {
+                gl::BindVertexArray(*vao);
+                gl::BindBuffer(gl::ARRAY_BUFFER, *buffer);
+                gl::BufferData(gl::ARRAY_BUFFER, (capacity * mem::size_of::<Particle>()) as isize, ptr::null(), gl::DYNAMIC_COPY);
+                gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, mem::offset_of!(Particle, position) as *const c_void);
+                gl::EnableVertexAttribArray(0);
+                gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, mem::offset_of!(Particle, rotation) as *const c_void);
+                gl::EnableVertexAttribArray(1);
+                gl::VertexAttribPointer(2, 1, gl::FLOAT, gl::FALSE, stride, mem::offset_of!(Particle, landed) as *const c_void);
+                gl::EnableVertexAttribArray(2);
+                gl::VertexAttribPointer(3, 1, gl::FLOAT, gl::FALSE, stride, mem::offset_of!(Particle, scale) as *const c_void);
+                gl::EnableVertexAttribArray(3);
+            }
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        (buffers, vaos)
+    }
+
+    /// Lets every mesh draw its variant from either of the buffers, the source index is the buffer index
+    fn add_instances_sources(meshes: &mut [Mesh], buffers: &[u32; 2]) {
+        let variants = meshes.len();
+        let particle_size = mem::size_of::<Particle>();
+        for (variant, mesh) in meshes.iter_mut().enumerate() {
+            mesh.clear_instances_sources();
+            for buffer in buffers {
+                mesh.add_instances_source(*buffer, variants * particle_size, variant * particle_size);
+            }
+        }
+    }
+
+    /// Number of snowflakes falling and drawn, not counting the retiring ones
+    pub fn count(&self) -> usize {
+        self.counts.count
+    }
+
+    /// Snowflakes landing per second, when there's no wind
+    pub fn intensity(&self) -> f32 {
+        self.parameters.intensity(self.counts.count)
+    }
+
+    /// Makes given number of snowflakes land per second, as far as the limits of set_count() allow.
+    /// Snowflakes which don't fall at all never land, their number doesn't change then.
+    pub fn set_intensity(&mut self, intensity: f32) {
+        if let Some(count) = self.parameters.count_for_intensity(intensity) {
+            self.set_count(count);
+        }
+    }
+
+    /// Changes the number of falling snowflakes, clamped to 1..=MAX_SNOWFLAKES.
+    /// New ones start above the box, so they reach it gradually, removed ones disappear at once and retire when they land.
+    pub fn set_count(&mut self, count: usize) {
+        let counts = self.counts.with_count(count);
+        self.reserve(counts.capacity);
+        if counts.simulated > self.counts.simulated {
+            let (bottom, top) = (self.parameters.min[1], self.parameters.max[1]);
+            let added = counts.simulated - self.counts.simulated;
+            let particles: Vec<Particle> = Snow::gen_snowflakes(&self.parameters, added, (top, 2. * top - bottom), &mut self.rng).iter()
+                .map(|snowflake| Particle::new(snowflake, self.material_id))
+                .collect();
+            self.write_particles(self.counts.simulated, &particles);
+        }
+        self.counts = counts;
+        // a query in progress counts snowflakes which may be falling again
+        self.retiring_query_pending = false;
+        self.update_shader.set_int("liveCount", counts.count as i32);
+    }
+
+    /// Makes the buffers have room for given number of snowflakes, keeping the simulated ones
+    fn reserve(&mut self, capacity: usize) {
+        if capacity <= self.counts.capacity {
+            return;
+        }
+        let (buffers, vaos) = Snow::create_buffers(capacity);
+        unsafe {
+            gl::BindBuffer(gl::COPY_READ_BUFFER, self.buffers[self.current]);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffers[self.current]);
+            gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0, (self.counts.simulated * mem::size_of::<Particle>()) as isize);
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            gl::DeleteVertexArrays(2, self.vaos.as_ptr());
+            gl::DeleteBuffers(2, self.buffers.as_ptr());
+        }
+        self.buffers = buffers;
+        self.vaos = vaos;
+        self.counts.capacity = capacity;
+        Snow::add_instances_sources(&mut self.meshes, &self.buffers);
+        self.use_current_buffer();
+    }
+
+    /// Stops simulating the retiring snowflakes once none of them is falling anymore.
+    /// The geometry shader passes on only the falling ones and a query counts them, its result is picked up in one of the next frames.
+    fn check_retiring(&mut self) {
+        if self.counts.count == self.counts.simulated {
+            return;
+        }
+        if self.retiring_query_pending {
+            let mut available = 0;
+            unsafe { gl::GetQueryObjectiv(self.retiring_query, gl::QUERY_RESULT_AVAILABLE, &mut available) }
+            if available == 0 {
+                return;
+            }
+            let mut falling = 0_u32;
+            unsafe { gl::GetQueryObjectuiv(self.retiring_query, gl::QUERY_RESULT, &mut falling) }
+            self.retiring_query_pending = false;
+            if falling == 0 {
+                self.counts = self.counts.retired();
+                return;
             }
-            let new_z_pos = snowflake.position.z + rng.sample(pos_offset_range);
-            snowflake.position = vec3(new_x_pos, new_y_pos, new_z_pos);
+        }
+        unsafe {
+            gl::UseProgram(self.retiring_shader.id);
+            gl::Enable(gl::RASTERIZER_DISCARD);
+            gl::BindVertexArray(self.vaos[self.current]);
+            gl::BeginQuery(gl::PRIMITIVES_GENERATED, self.retiring_query);
+            gl::DrawArrays(gl::POINTS, self.counts.count as i32, (self.counts.simulated - self.counts.count) as i32);
+            gl::EndQuery(gl::PRIMITIVES_GENERATED);
+            gl::BindVertexArray(0);
+            gl::Disable(gl::RASTERIZER_DISCARD);
+        }
+        self.retiring_query_pending = true;
+    }
 
-            let new_x_rot = snowflake.rotation.x + Rad(rng.sample(rot_angle_range));
-            let new_y_rot = snowflake.rotation.y + Rad(rng.sample(rot_angle_range));
-            let new_z_rot = snowflake.rotation.z + Rad(rng.sample(rot_angle_range));
-            snowflake.rotation = vec3(new_x_rot, new_y_rot, new_z_rot);
+    /// Stores the particles in the current buffer starting with the first-th snowflake
+    fn write_particles(&self, first: usize, particles: &[Particle]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffers[self.current]);
+            gl::BufferSubData(gl::ARRAY_BUFFER, (first * mem::size_of::<Particle>()) as isize, mem::size_of_val(particles) as isize,
+                              particles.as_ptr() as *const c_void);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         }
     }
 
-    fn gen_instances(&self) -> Vec<Instance> {
-        let mut instances: Vec<Instance> = Vec::with_capacity(MAX_SNOWFLAKES);
-        for i in 0..MAX_SNOWFLAKES {
-            let snowflake = &self.snowflakes[i];
-            let rotation = Matrix4::from(Euler { x: snowflake.rotation.x, y: snowflake.rotation.y, z: snowflake.rotation.z });
-            let translation = Matrix4::from_translation(snowflake.position);
-            let model = translation * rotation;
-            instances.push(Instance { model, material_id: self.material_id });
+    fn use_current_buffer(&mut self) {
+        for mesh in &mut self.meshes {
+            mesh.use_instances_source(self.current);
         }
-        instances
     }
 }
 
 impl Model for Snow {
-    fn next_frame(&mut self) {
-        self.move_snowflakes();
-        let instances = self.gen_instances();
-        self.mesh.fill_instances_vbo(&instances);
+    fn name(&self) -> &'static str {
+        "snow"
+    }
+
+    fn blow(&mut self, wind: &Wind) {
+        wind.set_uniforms(&self.update_shader);
+    }
+
+    fn next_frame(&mut self, dt: f32) {
+        self.update_shader.set_float("dt", dt);
+        self.update_shader.set_int("seed", self.rng.gen());
+        let next = 1 - self.current;
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + SURFACE_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, self.surface_texture);
+        }
+        self.spheres_buffer.bind(SPHERES_TEXTURE_UNIT);
+        self.frusta_buffer.bind(FRUSTA_TEXTURE_UNIT);
+        unsafe {
+            gl::UseProgram(self.update_shader.id);
+            gl::Enable(gl::RASTERIZER_DISCARD);
+            gl::BindVertexArray(self.vaos[self.current]);
+            gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.buffers[next]);
+            gl::BeginTransformFeedback(gl::POINTS);
+            gl::DrawArrays(gl::POINTS, 0, self.counts.simulated as i32);
+            gl::EndTransformFeedback();
+            gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, 0);
+            gl::BindVertexArray(0);
+            gl::Disable(gl::RASTERIZER_DISCARD);
+        }
+        self.current = next;
+        self.use_current_buffer();
+        self.check_retiring();
+        if let Some(cover) = &mut self.cover {
+            cover.accumulate(self.vaos[self.current], self.counts.simulated, dt);
+        }
+    }
+
+    fn draw(&mut self, shader: &Shader) -> usize {
+        // snowflakes are flat, both of their sides are visible
+        unsafe { gl::Disable(gl::CULL_FACE) }
+        let variants = self.meshes.len();
+        for (variant, mesh) in self.meshes.iter_mut().enumerate() {
+            // every variant-th snowflake starting with the variant-th one
+            mesh.draw_instances(shader, (self.counts.count + variants - 1 - variant) / variants);
+        }
+        unsafe { gl::Enable(gl::CULL_FACE) }
+        variants + self.cover.as_mut().map_or(0, |cover| cover.draw(shader))
+    }
+}
+
+/// Texels of the collider buffers, see snow_update.vert: a sphere is its center and radius grown by the margin,
+/// a frustum is its bottom center and height followed by its bottom and top radius
+fn collider_texels(colliders: &[Collider], sphere_margin: f32) -> (Vec<[f32; 4]>, Vec<[[f32; 4]; 2]>) {
+    let (mut spheres, mut frusta) = (vec![], vec![]);
+    for collider in colliders {
+        match *collider {
+            Collider::Sphere { center, radius } => spheres.push([center.x, center.y, center.z, radius + sphere_margin]),
+            Collider::Frustum { bottom, height, bottom_radius, top_radius } =>
+                frusta.push([[bottom.x, bottom.y, bottom.z, height], [bottom_radius, top_radius, 0., 0.]]),
+        }
+    }
+    (spheres, frusta)
+}
+
+impl Drop for Snow {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(2, self.vaos.as_ptr());
+            gl::DeleteBuffers(2, self.buffers.as_ptr());
+            gl::DeleteTextures(1, &self.surface_texture);
+            gl::DeleteQueries(1, &self.retiring_query);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use cgmath::{Point3, Vector4};
+
+    use crate::collision::Collider;
+    use crate::model::Instance;
+    use crate::xmas_tree::snow::{collider_texels, FEEDBACK_VARYINGS, MAX_SNOWFLAKES, Particle, SnowCounts, SnowParameters};
+
+    fn parameters(fall_velocity: f32) -> SnowParameters {
+        SnowParameters {
+            count: 100, min: [-1., 0., -1.], max: [1., 4., 1.], fall_velocity, max_random_offset: 0., max_random_rotation: 0.,
+            cover: None, variants: 1, min_scale: 1., max_scale: 1.,
+        }
+    }
+
+    #[test]
+    fn count_stays_within_bounds() {
+        let counts = SnowCounts::new(10);
+        assert_eq!(counts.with_count(0).count, 1);
+        assert_eq!(counts.with_count(MAX_SNOWFLAKES + 1), SnowCounts { count: MAX_SNOWFLAKES, simulated: MAX_SNOWFLAKES, capacity: MAX_SNOWFLAKES });
+    }
+
+    #[test]
+    fn retiring_snowflakes_fall_again_when_count_grows() {
+        let retiring = SnowCounts::new(100).with_count(40);
+        assert_eq!(retiring, SnowCounts { count: 40, simulated: 100, capacity: 100 });
+        assert_eq!(retiring.with_count(70), SnowCounts { count: 70, simulated: 100, capacity: 100 });
+        assert_eq!(retiring.with_count(120), SnowCounts { count: 120, simulated: 120, capacity: 200 });
+        assert_eq!(retiring.retired(), SnowCounts { count: 40, simulated: 40, capacity: 100 });
+    }
+
+    #[test]
+    fn capacity_at_least_doubles() {
+        let counts = SnowCounts::new(100);
+        assert_eq!(counts.with_count(101).capacity, 200);
+        assert_eq!(counts.with_count(350).capacity, 350);
+        assert_eq!(SnowCounts::new(MAX_SNOWFLAKES - 1).with_count(MAX_SNOWFLAKES).capacity, MAX_SNOWFLAKES);
+    }
+
+    #[test]
+    fn intensity_gives_count() {
+        let parameters = parameters(2.);
+        assert_eq!(parameters.intensity(100), 50.);
+        assert_eq!(parameters.count_for_intensity(50.), Some(100));
+        assert_eq!(parameters.count_for_intensity(-1.), Some(0));
+        assert_eq!(self::parameters(0.).count_for_intensity(50.), None);
+    }
+
+    #[test]
+    fn keeps_all_colliders() {
+        let mut colliders: Vec<Collider> = (0..100).map(|i| Collider::Sphere { center: Point3::new(i as f32, 0., 0.), radius: 0.5 }).collect();
+        colliders.extend((0..20).map(|i| Collider::Frustum { bottom: Point3::new(0., i as f32, 1.), height: 1., bottom_radius: 2., top_radius: 1.5 }));
+        let (spheres, frusta) = collider_texels(&colliders, 0.1);
+        assert_eq!((spheres.len(), frusta.len()), (100, 20));
+        assert_eq!(spheres[99], [99., 0., 0., 0.6]);
+        assert_eq!(frusta[19], [[0., 19., 1., 1.], [2., 1.5, 0., 0.]]);
+    }
+
+    #[test]
+    fn particle_is_laid_out_like_feedback_varyings() {
+        let instance = mem::offset_of!(Particle, instance);
+        let field_offsets = [
+            instance + mem::offset_of!(Instance, model),
+            instance + mem::offset_of!(Instance, material_id),
+            instance + mem::offset_of!(Instance, emission),
+            mem::offset_of!(Particle, position),
+            mem::offset_of!(Particle, rotation),
+            mem::offset_of!(Particle, landed),
+            mem::offset_of!(Particle, scale),
+        ];
+        let mut offset = 0;
+        for ((name, size), field_offset) in FEEDBACK_VARYINGS.iter().zip(field_offsets.iter()) {
+            assert_eq!(offset, *field_offset, "{}", name);
+            offset += size;
+        }
+        assert_eq!(mem::size_of::<Particle>(), offset);
     }
 
-    fn draw(&mut self, shader: &Shader) {
-        self.mesh.draw_instances(shader, MAX_SNOWFLAKES);
+    #[test]
+    fn instance_is_laid_out_like_instance_attributes() {
+        // model matrix in four vec4 columns followed by material id and emission, see Mesh::point_instance_attributes()
+        let vec4_size = mem::size_of::<Vector4<f32>>();
+        assert_eq!(mem::offset_of!(Instance, model), 0);
+        assert_eq!(mem::offset_of!(Instance, material_id), 4 * vec4_size);
+        assert_eq!(mem::offset_of!(Instance, emission), 4 * vec4_size + 4);
+        assert_eq!(mem::size_of::<Instance>(), Instance::size());
+        assert_eq!(mem::offset_of!(Particle, position), Instance::size());
     }
 }
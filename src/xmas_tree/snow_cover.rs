@@ -0,0 +1,386 @@
+extern crate gl;
+
+use std::{mem, ptr, slice};
+
+use gl::types::GLsync;
+
+use cgmath::{Matrix4, Point3, vec3};
+use cgmath::prelude::*;
+use serde::Deserialize;
+
+use crate::material::MaterialId;
+use crate::model::Instance;
+use crate::shader::Shader;
+use crate::xmas_tree::mesh::{Mesh, Vertex};
+
+/// Number of cells of the surface and the snow depth along each axis
+pub const SURFACE_RESOLUTION: usize = 128;
+/// Every landed snowflake covers that many cells in each direction
+const FLAKE_SPLAT_SIZE: f32 = 3.;
+/// Simulated time between rebuilds of the snow layer from the depth read back from the GPU
+const REBUILD_INTERVAL: f32 = 0.25;
+/// Thinner snow isn't drawn, it would fight with the surface below it
+const MIN_VISIBLE_DEPTH: f32 = 0.005;
+/// Neighbouring cells whose surfaces differ more than that aren't connected, e.g. a branch and the ground below it
+const MAX_SURFACE_STEP: f32 = 0.5;
+
+/// How landed snow piles up, depths are in world units, melt rate in world units per second
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnowCoverParameters {
+    /// depth added by a single snowflake
+    pub flake_depth: f32,
+    pub max_depth: f32,
+    pub melt_rate: f32,
+}
+
+/// Height of the highest surface snow lands on, over a horizontal rectangle divided into resolution x resolution cells.
+/// Rows go along Z axis, columns along X axis.
+#[derive(Debug, Clone)]
+pub struct SurfaceMap {
+    /// X and Z of the corners
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub resolution: usize,
+    pub heights: Vec<f32>,
+}
+
+impl SurfaceMap {
+    /// Flat surface at the floor height
+    pub fn new(min: [f32; 2], max: [f32; 2], resolution: usize, floor: f32) -> Self {
+        SurfaceMap { min, max, resolution, heights: vec![floor; resolution * resolution] }
+    }
+
+    pub fn cell_size(&self) -> (f32, f32) {
+        ((self.max[0] - self.min[0]) / self.resolution as f32, (self.max[1] - self.min[1]) / self.resolution as f32)
+    }
+
+    /// X and Z of the middle of the cell
+    pub fn cell_center(&self, column: usize, row: usize) -> (f32, f32) {
+        let (width, depth) = self.cell_size();
+        (self.min[0] + (column as f32 + 0.5) * width, self.min[1] + (row as f32 + 0.5) * depth)
+    }
+
+    pub fn height(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.resolution + column]
+    }
+
+    /// Raises the surface to the triangle in the cells whose middle it covers, triangles facing down are skipped
+    pub fn add_triangle(&mut self, triangle: &[Point3<f32>; 3]) {
+        let [a, b, c] = *triangle;
+        if (b - a).cross(c - a).y <= 0. {
+            return;
+        }
+        let (width, depth) = self.cell_size();
+        let cells = |min: f32, max: f32, origin: f32, size: f32| {
+            let first = ((min - origin) / size - 0.5).ceil().max(0.) as usize;
+            let last = ((max - origin) / size - 0.5).floor().min(self.resolution as f32 - 1.);
+            if last < 0. { 0..0 } else { first..last as usize + 1 }
+        };
+        let columns = cells(a.x.min(b.x).min(c.x), a.x.max(b.x).max(c.x), self.min[0], width);
+        let rows = cells(a.z.min(b.z).min(c.z), a.z.max(b.z).max(c.z), self.min[1], depth);
+        // barycentric coordinates of the point projected onto XZ plane
+        let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+        for row in rows {
+            for column in columns.clone() {
+                let (x, z) = self.cell_center(column, row);
+                let u = ((x - a.x) * (c.z - a.z) - (c.x - a.x) * (z - a.z)) / area;
+                let v = ((b.x - a.x) * (z - a.z) - (x - a.x) * (b.z - a.z)) / area;
+                if u < 0. || v < 0. || u + v > 1. {
+                    continue;
+                }
+                let y = a.y + u * (b.y - a.y) + v * (c.y - a.y);
+                let height = &mut self.heights[row * self.resolution + column];
+                *height = height.max(y);
+            }
+        }
+    }
+
+    /// Left, front, right and back edge as used by the shaders
+    pub fn area(&self) -> [f32; 4] {
+        [self.min[0], self.min[1], self.max[0], self.max[1]]
+    }
+}
+
+/// Snow piling up where snowflakes land. The depth is kept in a texture on the GPU, landed snowflakes are added to it
+/// and melting snow is subtracted every frame, the snow layer is rebuilt from it a few times per second.
+/// The depth is read back into a pixel buffer and used only once the GPU is done with it, so that reading never stalls the pipeline.
+pub struct SnowCover {
+    parameters: SnowCoverParameters,
+    surface: SurfaceMap,
+    mesh: Mesh,
+    /// depth as a fraction of max_depth, normalized format keeps it between 0 and 1 while blending
+    depth_texture: u32,
+    fbo: u32,
+    /// pixel buffer the depth is read back into
+    read_buffer: u32,
+    /// signalled when the depth in the read buffer is ready
+    read_fence: Option<GLsync>,
+    deposit_shader: Shader,
+    melt_shader: Shader,
+    /// the melting pass doesn't read any vertex attributes, but drawing needs a VAO
+    empty_vao: u32,
+    since_rebuild: f32,
+}
+
+impl SnowCover {
+    pub fn new(parameters: SnowCoverParameters, surface: SurfaceMap, material_id: MaterialId) -> Self {
+        let (vertices, indices) = cover_geometry(&surface, &vec![0.; surface.heights.len()]);
+        let mesh = Mesh::new(vertices, indices, 1);
+        mesh.fill_instances_vbo(&[Instance { model: Matrix4::identity(), material_id, emission: 0. }]);
+        let depth_texture = Self::create_depth_texture(surface.resolution as i32);
+        let fbo = Self::create_fbo(depth_texture);
+        let read_buffer = Self::create_read_buffer(surface.resolution);
+        let deposit_shader = Shader::new("src/xmas_tree/shaders/snow_deposit.vert", "src/xmas_tree/shaders/snow_amount.frag");
+        deposit_shader.set_vec4("area", &surface.area());
+        deposit_shader.set_float("amount", parameters.flake_depth / parameters.max_depth);
+        let melt_shader = Shader::new("src/xmas_tree/shaders/snow_melt.vert", "src/xmas_tree/shaders/snow_amount.frag");
+        let mut empty_vao = 0_u32;
+        unsafe { gl::GenVertexArrays(1, &mut empty_vao) }
+        let mut cover = SnowCover { parameters, surface, mesh, depth_texture, fbo, read_buffer, read_fence: None, deposit_shader, melt_shader, empty_vao, since_rebuild: 0. };
+        cover.reset();
+        cover
+    }
+
+    fn create_depth_texture(resolution: i32) -> u32 {
+        unsafe {
+            let mut texture = 0_u32;
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R16 as i32, resolution, resolution, 0, gl::RED, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            texture
+        }
+    }
+
+    fn create_fbo(texture: u32) -> u32 {
+        unsafe {
+            let mut fbo = 0_u32;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            fbo
+        }
+    }
+
+    fn create_read_buffer(resolution: usize) -> u32 {
+        unsafe {
+            let mut buffer = 0_u32;
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+            gl::BufferData(gl::PIXEL_PACK_BUFFER, (resolution * resolution * mem::size_of::<f32>()) as isize, ptr::null(), gl::STREAM_READ);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            buffer
+        }
+    }
+
+    /// Melts the snow for dt seconds and adds snowflakes marked as landed, read with the given VAO
+    pub fn accumulate(&mut self, snowflakes_vao: u32, count: usize, dt: f32) {
+        self.melt_shader.set_float("amount", self.parameters.melt_rate * dt / self.parameters.max_depth);
+        self.draw_into_depth(|| unsafe {
+            gl::BlendEquation(gl::FUNC_REVERSE_SUBTRACT);
+            gl::BindVertexArray(self.empty_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            gl::BlendEquation(gl::FUNC_ADD);
+            gl::UseProgram(self.deposit_shader.id);
+            gl::PointSize(FLAKE_SPLAT_SIZE);
+            gl::BindVertexArray(snowflakes_vao);
+            gl::DrawArrays(gl::POINTS, 0, count as i32);
+            gl::BindVertexArray(0);
+        });
+        self.since_rebuild += dt;
+        self.finish_reading();
+        if self.since_rebuild >= REBUILD_INTERVAL && self.read_fence.is_none() {
+            self.start_reading();
+        }
+    }
+
+    /// Runs f with the depth texture as the target of additive blending, the melting shader is in use
+    fn draw_into_depth<F: FnOnce()>(&self, f: F) {
+        let mut previous_fbo = 0;
+        let mut previous_viewport = [0; 4];
+        let resolution = self.surface.resolution as i32;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_fbo);
+            gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, resolution, resolution);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+            gl::UseProgram(self.melt_shader.id);
+        }
+        f();
+        unsafe {
+            gl::BlendEquation(gl::FUNC_ADD);
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous_fbo as u32);
+            let v = previous_viewport;
+            gl::Viewport(v[0], v[1], v[2], v[3]);
+        }
+    }
+
+    /// Removes all snow
+    pub fn reset(&mut self) {
+        unsafe {
+            let mut previous_fbo = 0;
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::ClearColor(0., 0., 0., 0.);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous_fbo as u32);
+        }
+        // depth being read was there before
+        self.cancel_reading();
+        self.rebuild(&vec![0.; self.surface.heights.len()]);
+    }
+
+    /// Starts copying the depth into the read buffer, finish_reading() picks it up when it's done
+    fn start_reading(&mut self) {
+        let resolution = self.surface.resolution as i32;
+        unsafe {
+            let mut previous_fbo = 0;
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous_fbo);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.read_buffer);
+            gl::ReadPixels(0, 0, resolution, resolution, gl::RED, gl::FLOAT, ptr::null_mut());
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous_fbo as u32);
+            self.read_fence = Some(gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+        }
+        self.since_rebuild = 0.;
+    }
+
+    /// Rebuilds the snow layer from the read buffer if the depth is there already
+    fn finish_reading(&mut self) {
+        let fence = match self.read_fence {
+            Some(fence) => fence,
+            None => return,
+        };
+        let status = unsafe { gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0) };
+        if status == gl::TIMEOUT_EXPIRED {
+            return;
+        }
+        self.cancel_reading();
+        let count = self.surface.heights.len();
+        let depths: Vec<f32> = unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.read_buffer);
+            let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, (count * mem::size_of::<f32>()) as isize, gl::MAP_READ_BIT) as *const f32;
+            let depths = if mapped.is_null() { vec![] } else { slice::from_raw_parts(mapped, count).to_vec() };
+            gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            depths
+        };
+        if depths.len() == count {
+            self.rebuild(&depths.iter().map(|depth| depth * self.parameters.max_depth).collect::<Vec<f32>>());
+        }
+    }
+
+    fn cancel_reading(&mut self) {
+        if let Some(fence) = self.read_fence.take() {
+            unsafe { gl::DeleteSync(fence) }
+        }
+    }
+
+    /// Makes the snow layer from the depth in world units
+    fn rebuild(&mut self, depths: &[f32]) {
+        let (vertices, indices) = cover_geometry(&self.surface, depths);
+        self.mesh.replace_geometry(&vertices, indices);
+    }
+
+    /// Returns the number of draw calls made, none while there's no snow lying anywhere
+    pub fn draw(&mut self, shader: &Shader) -> usize {
+        if self.mesh.is_empty() {
+            return 0;
+        }
+        self.mesh.draw_single(shader);
+        1
+    }
+}
+
+impl Drop for SnowCover {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.depth_texture);
+            gl::DeleteBuffers(1, &self.read_buffer);
+            gl::DeleteVertexArrays(1, &self.empty_vao);
+        }
+        self.cancel_reading();
+    }
+}
+
+/// Snow layer lying on the surface: a grid with a vertex in the middle of every cell, lifted by the depth of snow there.
+/// Only quads with snow in all corners are kept, unless they span a step of the surface.
+fn cover_geometry(surface: &SurfaceMap, depths: &[f32]) -> (Vec<Vertex>, Vec<u32>) {
+    let n = surface.resolution;
+    let top = |column: usize, row: usize| surface.height(column, row) + depths[row * n + column];
+    let connected = |a: (usize, usize), b: (usize, usize)| (surface.height(a.0, a.1) - surface.height(b.0, b.1)).abs() <= MAX_SURFACE_STEP;
+    let (width, depth) = surface.cell_size();
+    let mut vertices = Vec::with_capacity(n * n);
+    for row in 0..n {
+        for column in 0..n {
+            // slope towards neighbours on the same surface, the ones behind a step are treated as level
+            let neighbour = |c: usize, r: usize| if connected((c, r), (column, row)) { top(c, r) } else { top(column, row) };
+            let left = neighbour(column.saturating_sub(1), row);
+            let right = neighbour((column + 1).min(n - 1), row);
+            let back = neighbour(column, row.saturating_sub(1));
+            let front = neighbour(column, (row + 1).min(n - 1));
+            let normal = vec3((left - right) / (2. * width), 1., (back - front) / (2. * depth)).normalize();
+            let (x, z) = surface.cell_center(column, row);
+            vertices.push(Vertex { position: Point3::new(x, top(column, row), z), normal });
+        }
+    }
+    let mut indices = vec![];
+    for row in 0..n.saturating_sub(1) {
+        for column in 0..n - 1 {
+            let corners = [(column, row), (column, row + 1), (column + 1, row), (column + 1, row + 1)];
+            let covered = corners.iter().all(|&(c, r)| depths[r * n + c] >= MIN_VISIBLE_DEPTH);
+            if !covered || !corners.iter().all(|&corner| connected(corner, corners[0])) {
+                continue;
+            }
+            let index = |(c, r): (usize, usize)| (r * n + c) as u32;
+            let [a, b, c, d] = [index(corners[0]), index(corners[1]), index(corners[2]), index(corners[3])];
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use crate::xmas_tree::snow_cover::{cover_geometry, SurfaceMap};
+
+    #[test]
+    fn raises_surface_under_upward_triangles() {
+        let mut surface = SurfaceMap::new([0., 0.], [4., 4.], 4, -1.);
+        // covers the middle of cells (0, 0), (0, 1) and (1, 0) only
+        surface.add_triangle(&[Point3::new(0., 2., 0.), Point3::new(0., 2., 2.5), Point3::new(2.5, 2., 0.)]);
+        // facing down, no effect
+        surface.add_triangle(&[Point3::new(0., 5., 0.), Point3::new(4., 5., 0.), Point3::new(0., 5., 4.)]);
+        assert_eq!(surface.heights, vec![2., 2., -1., -1.,
+                                         2., -1., -1., -1.,
+                                         -1., -1., -1., -1.,
+                                         -1., -1., -1., -1.]);
+    }
+
+    #[test]
+    fn covers_only_snowy_cells_on_the_same_surface() {
+        let mut surface = SurfaceMap::new([0., 0.], [3., 3.], 3, 0.);
+        let depths = [0.1; 9];
+        assert_eq!(cover_geometry(&surface, &[0.; 9]).1.len(), 0);
+        assert_eq!(cover_geometry(&surface, &depths).1.len(), 4 * 6);
+        // a step in the corner cell cuts off the quad it belongs to
+        surface.heights[8] = 2.;
+        let (vertices, indices) = cover_geometry(&surface, &depths);
+        assert_eq!(indices.len(), 3 * 6);
+        assert!((vertices[8].position.y - 2.1).abs() < 1e-6);
+    }
+}
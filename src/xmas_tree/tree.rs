@@ -1,23 +1,41 @@
 use cgmath::{Matrix4, Point3, vec3, Vector3};
+use cgmath::prelude::*;
 
-use crate::material::{Material, Materials};
-use crate::model::{Instance, Model};
+use crate::collision::Collider;
+use crate::material::{Material, MaterialId, Materials};
+use crate::model::{HIGHLIGHT_EMISSION, Instance, Model};
+use crate::picking::{closest, Ray};
 use crate::shader::Shader;
 use crate::xmas_tree::mesh::{Mesh, Vertex};
 
+/// Number of frusta the tree is approximated with for snow collisions
+const COLLIDER_SECTIONS: usize = 8;
+
 pub struct Tree {
     meshes: Vec<Mesh>,
+    /// material of every mesh
+    material_ids: Vec<MaterialId>,
+    transform: Matrix4<f32>,
+    /// all triangles of the tree already transformed, used for picking
+    triangles: Vec<[Point3<f32>; 3]>,
+    colliders: Vec<Collider>,
 }
 
 impl Tree {
-    pub fn new(materials: &mut Materials) -> Self {
-        Self::from_model(materials)
+    pub fn new(materials: &mut Materials, path: &str, transform: Matrix4<f32>) -> Self {
+        Self::from_model(materials, path, transform)
     }
 
-    fn from_model(materials: &mut Materials) -> Self {
-        let tree = tobj::load_obj("models/tree.obj");
+    fn from_model(materials: &mut Materials, path: &str, transform: Matrix4<f32>) -> Self {
+        let tree = tobj::load_obj(path);
         let (models, model_materials) = tree.unwrap();
+        // every material from the MTL file is added once, even when several meshes use it
+        let model_material_ids: Vec<MaterialId> = model_materials.iter()
+            .map(|m| materials.add(Material { ambient: Vector3::from(m.ambient), diffuse: Vector3::from(m.diffuse), specular: Vector3::from(m.specular), shininess: m.shininess }))
+            .collect();
         let mut meshes: Vec<Mesh> = vec![];
+        let mut material_ids: Vec<MaterialId> = vec![];
+        let mut triangles: Vec<[Point3<f32>; 3]> = vec![];
         for mi in 0..models.len() {
             let mut vertices: Vec<Vertex> = vec![];
             let mut indices: Vec<u32> = vec![];
@@ -28,28 +46,69 @@ impl Tree {
                 vertices.push(Vertex { position, normal });
             }
             indices.extend(mesh.indices.iter());
+            for triangle in indices.chunks(3) {
+                let corner = |i: usize| transform.transform_point(vertices[triangle[i] as usize].position);
+                triangles.push([corner(0), corner(1), corner(2)]);
+            }
 
-            let material = &model_materials[models[mi].mesh.material_id.unwrap()];
-            let my_material = Material{ambient: Vector3::from(material.ambient), diffuse: Vector3::from(material.diffuse), specular: Vector3::from(material.specular), shininess: material.shininess};
-            let material_id = materials.add(my_material);
-            let mesh = Mesh::new(vertices, indices, 1);
-            let scaling = Matrix4::from_nonuniform_scale(1.8, 1., 1.8);
-            mesh.fill_instances_vbo(&vec![Instance { model: scaling, material_id }]);
-            meshes.push(mesh);
+            material_ids.push(model_material_ids[models[mi].mesh.material_id.unwrap()]);
+            meshes.push(Mesh::new(vertices, indices, 1));
         }
 
-        Self { meshes }
+        let corners: Vec<Point3<f32>> = triangles.iter().flat_map(|t| t.iter().copied()).collect();
+        let colliders = Collider::envelope(&corners, COLLIDER_SECTIONS);
+        let tree = Self { meshes, material_ids, transform, triangles, colliders };
+        tree.fill_instances(0.);
+        tree
+    }
+
+    fn fill_instances(&self, emission: f32) {
+        for (mesh, material_id) in self.meshes.iter().zip(&self.material_ids) {
+            mesh.fill_instances_vbo(&[Instance { model: self.transform, material_id: *material_id, emission }]);
+        }
+    }
+}
+
+/// Number of materials the tree from given OBJ file adds to the scene, they're defined in its MTL file.
+/// Every mesh has to use one of them.
+pub fn material_count(path: &str) -> Result<usize, String> {
+    let (models, materials) = tobj::load_obj(path).map_err(|e| format!("{}: {:?}", path, e))?;
+    match models.iter().find(|m| m.mesh.material_id.is_none_or(|id| id >= materials.len())) {
+        Some(model) => Err(format!("{}: mesh '{}' has no material from the MTL file", path, model.name)),
+        None => Ok(materials.len()),
     }
 }
 
 impl Model for Tree {
-    fn next_frame(&mut self) {
+    fn name(&self) -> &'static str {
+        "tree"
+    }
+
+    fn next_frame(&mut self, _dt: f32) {
         // nothing changes
     }
 
-    fn draw(&mut self, shader: &Shader) {
+    fn draw(&mut self, shader: &Shader) -> usize {
         for mesh in &self.meshes {
             mesh.draw_single(shader);
         }
+        self.meshes.len()
+    }
+
+    fn snow_surface(&self) -> Vec<[Point3<f32>; 3]> {
+        self.triangles.clone()
+    }
+
+    fn colliders(&self) -> Vec<Collider> {
+        self.colliders.clone()
+    }
+
+    /// The whole tree is a single instance
+    fn pick(&self, ray: &Ray) -> Option<(usize, f32)> {
+        closest(self.triangles.iter().map(|t| ray.intersect_triangle(t))).map(|(_, t)| (0, t))
+    }
+
+    fn highlight(&mut self, instance: Option<usize>) {
+        self.fill_instances(instance.map_or(0., |_| HIGHLIGHT_EMISSION));
     }
 }